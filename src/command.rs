@@ -0,0 +1,154 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// A command given to Tailor in a pull request comment.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Exempts the pull request from the rules (or "all" rules)
+    Disable {
+        rules: Vec<String>,
+        reason: Option<String>,
+    },
+    /// Revokes earlier exemptions from the rules (or "all" rules)
+    Enable { rules: Vec<String> },
+    /// Evaluates the pull request again
+    Recheck,
+    /// Replies with the description and expression of a rule
+    Explain { rule: String },
+    /// Anything else following "/tailor"
+    Unknown(String),
+}
+
+const PREFIX: &str = "/tailor";
+
+/// Finds all of the commands in a comment. Each command must be on its own line, which starts with
+/// "/tailor". For compatibility, lines starting with "tailor disable" are also accepted.
+pub fn parse(comment: &str) -> Vec<Command> {
+    comment
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with(PREFIX) {
+                let rest = &line[PREFIX.len()..];
+                if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                    return Some(parse_command(rest.trim()));
+                }
+            } else if line.starts_with("tailor disable") {
+                return Some(parse_command(&line["tailor ".len()..]));
+            }
+            None
+        })
+        .collect()
+}
+
+fn parse_command(command: &str) -> Command {
+    let (name, args) = match command.find(char::is_whitespace) {
+        Some(i) => (&command[..i], command[i..].trim()),
+        None => (command, ""),
+    };
+
+    match name {
+        "disable" => {
+            let (rules, reason) = match args.find("reason:") {
+                Some(i) => (&args[..i], Some(args[i + "reason:".len()..].trim())),
+                None => (args, None),
+            };
+            match split_rules(rules) {
+                Some(rules) => Command::Disable {
+                    rules,
+                    reason: reason.filter(|r| !r.is_empty()).map(String::from),
+                },
+                None => Command::Unknown(command.to_string()),
+            }
+        }
+        "enable" => match split_rules(args) {
+            Some(rules) => Command::Enable { rules },
+            None => Command::Unknown(command.to_string()),
+        },
+        "recheck" if args.is_empty() => Command::Recheck,
+        "explain" if !args.is_empty() => Command::Explain {
+            rule: args.to_string(),
+        },
+        _ => Command::Unknown(command.to_string()),
+    }
+}
+
+fn split_rules(rules: &str) -> Option<Vec<String>> {
+    let rules: Vec<String> = rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(String::from)
+        .collect();
+
+    if rules.is_empty() {
+        None
+    } else {
+        Some(rules)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("/tailor disable commit title"),
+            vec![Command::Disable {
+                rules: vec!["commit title".into()],
+                reason: None,
+            }]
+        );
+        assert_eq!(
+            parse("Looks fine.\n\n  /tailor disable commit title, commit description reason: imported history  \nThanks"),
+            vec![Command::Disable {
+                rules: vec!["commit title".into(), "commit description".into()],
+                reason: Some("imported history".into()),
+            }]
+        );
+        assert_eq!(
+            parse("/tailor enable all\n/tailor recheck\n/tailor explain commit title"),
+            vec![
+                Command::Enable {
+                    rules: vec!["all".into()],
+                },
+                Command::Recheck,
+                Command::Explain {
+                    rule: "commit title".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            parse("tailor disable commit title"),
+            vec![Command::Disable {
+                rules: vec!["commit title".into()],
+                reason: None,
+            }]
+        );
+        assert_eq!(
+            parse("/tailor disable\n/tailor frobnicate\n/tailor"),
+            vec![
+                Command::Unknown("disable".into()),
+                Command::Unknown("frobnicate".into()),
+                Command::Unknown("".into()),
+            ]
+        );
+        assert_eq!(
+            parse("Please run /tailor recheck\n/tailored suits\ntailor is neat"),
+            vec![]
+        );
+    }
+}
@@ -12,14 +12,485 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[derive(Debug, Deserialize)]
+use errors::*;
+use github::types::Permission;
+use serde::de::{self, Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Deserialize)]
 pub struct Config {
+    /// Another configuration from which rules are inherited
+    #[serde(default)]
+    pub extends: Option<Source>,
+    /// The names of inherited rules which should not be run
+    #[serde(default)]
+    pub disable: Vec<String>,
+    /// Who may exempt pull requests from the rules, unless overridden by a rule
+    #[serde(default)]
+    pub exemptions: Option<Authority>,
+    /// How the results are reported, in addition to the status
+    #[serde(default)]
+    pub report: Option<Reporting>,
+    #[serde(default)]
     pub rules: Vec<Rule>,
 }
 
+impl Config {
+    /// Merges this configuration on top of the one that it extends. Rules are matched by name and
+    /// the local definition always wins. Inherited rules that are disabled are dropped.
+    pub fn merge(self, parent: Config) -> Config {
+        let mut rules: Vec<Rule> = parent
+            .rules
+            .into_iter()
+            .filter(|rule| !self.disable.contains(&rule.name))
+            .collect();
+
+        for rule in self.rules {
+            match rules.iter().position(|r| r.name == rule.name) {
+                Some(i) => rules[i] = rule,
+                None => rules.push(rule),
+            }
+        }
+
+        Config {
+            extends: None,
+            disable: Vec::new(),
+            exemptions: self.exemptions.or(parent.exemptions),
+            report: self.report.or(parent.report),
+            rules,
+        }
+    }
+
+    /// Who may exempt pull requests from the named rule. Rules which don't exist fall back to the
+    /// global authority.
+    pub fn authority(&self, rule: &str) -> Authority {
+        self.rules
+            .iter()
+            .find(|r| r.name == rule)
+            .and_then(|r| r.exemptions.clone())
+            .or_else(|| self.exemptions.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// The users who are allowed to grant (and revoke) exemptions. A user is allowed if any of the
+/// criteria are met.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Authority {
+    /// The minimum permission on the repository
+    #[serde(default)]
+    pub permission: Option<Permission>,
+    /// The users who are always allowed
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// The teams (either "org/team" or "team", within the repository owner) whose members are
+    /// always allowed
+    #[serde(default)]
+    pub teams: Vec<String>,
+}
+
+impl Default for Authority {
+    fn default() -> Authority {
+        Authority {
+            permission: Some(Permission::Admin),
+            users: Vec::new(),
+            teams: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Authority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(permission) = self.permission {
+            criteria.push(format!("{:?} permission", permission).to_lowercase());
+        }
+        if !self.users.is_empty() {
+            criteria.push(format!("being one of {}", self.users.join(", ")));
+        }
+        if !self.teams.is_empty() {
+            criteria.push(format!("membership in {}", self.teams.join(", ")));
+        }
+
+        if criteria.is_empty() {
+            write!(f, "nobody")
+        } else {
+            write!(f, "{}", criteria.join(" or "))
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Rule {
     pub name: String,
     pub description: String,
     pub expression: String,
+    #[serde(default)]
+    pub severity: Severity,
+    #[serde(default)]
+    pub when: Condition,
+    /// Who may exempt pull requests from this rule
+    #[serde(default)]
+    pub exemptions: Option<Authority>,
+    /// Whether exemptions from this rule still apply after more commits are pushed
+    #[serde(default)]
+    pub sticky: bool,
+}
+
+/// Restricts the pull requests to which a rule applies. Each of the lists is ignored if it is
+/// empty; otherwise, all of them must be satisfied for the rule to apply.
+#[derive(Debug, Default, Deserialize)]
+pub struct Condition {
+    /// Globs, one of which must match the name of the base branch
+    #[serde(default)]
+    pub base: Vec<String>,
+    /// Globs, one of which must match the name of the head branch
+    #[serde(default)]
+    pub head: Vec<String>,
+    /// Globs, one of which must match one of the changed files
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Labels which must all be present on the pull request
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Labels which must all be absent from the pull request
+    #[serde(default)]
+    pub without_labels: Vec<String>,
+    /// Users, one of which must have opened the pull request
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// Users, none of which may have opened the pull request
+    #[serde(default)]
+    pub without_authors: Vec<String>,
+}
+
+/// The properties of a pull request against which a rule's condition is checked.
+pub struct Target {
+    pub base: String,
+    pub head: String,
+    pub files: Vec<String>,
+    pub labels: Vec<String>,
+    pub author: String,
+}
+
+impl Condition {
+    pub fn applies(&self, target: &Target) -> bool {
+        let any_glob = |globs: &[String], text: &str| globs.iter().any(|g| glob(g, text));
+
+        (self.base.is_empty() || any_glob(&self.base, &target.base))
+            && (self.head.is_empty() || any_glob(&self.head, &target.head))
+            && (self.paths.is_empty() || target.files.iter().any(|f| any_glob(&self.paths, f)))
+            && self.labels.iter().all(|l| target.labels.contains(l))
+            && !self.without_labels.iter().any(|l| target.labels.contains(l))
+            && (self.authors.is_empty() || self.authors.contains(&target.author))
+            && !self.without_authors.contains(&target.author)
+    }
+}
+
+/// Matches text against a glob, where "*" matches any characters other than "/", "**" matches
+/// any characters, and "?" matches a single character other than "/".
+fn glob(pattern: &str, text: &str) -> bool {
+    if pattern.starts_with("**/") {
+        let rest = &pattern[3..];
+        return glob(rest, text)
+            || text.match_indices('/').any(|(i, _)| glob(rest, &text[i + 1..]));
+    }
+
+    if pattern.starts_with("**") {
+        let rest = &pattern[2..];
+        return text.char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(text.len()))
+            .any(|i| glob(rest, &text[i..]));
+    }
+
+    let mut chars = pattern.chars();
+    match (chars.next(), text.chars().next()) {
+        (None, _) => text.is_empty(),
+        (Some('*'), _) => {
+            for (i, c) in text.char_indices() {
+                if glob(chars.as_str(), &text[i..]) {
+                    return true;
+                }
+                if c == '/' {
+                    return false;
+                }
+            }
+            glob(chars.as_str(), "")
+        }
+        (Some('?'), Some(c)) if c != '/' => glob(chars.as_str(), &text[c.len_utf8()..]),
+        (Some(p), Some(c)) if p == c => glob(chars.as_str(), &text[c.len_utf8()..]),
+        _ => false,
+    }
+}
+
+/// How the results are reported to the author of the pull request.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Reporting {
+    /// Only the status (or check run) is updated
+    #[serde(rename = "status")]
+    Status,
+    /// A summary comment is also posted on the pull request and kept up to date
+    #[serde(rename = "comment")]
+    Comment,
+}
+
+/// How a rule's failure affects the status of the pull request.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Severity {
+    /// The failure is reported and fails the status
+    #[serde(rename = "error")]
+    Error,
+    /// The failure is reported and counted in the status, but does not fail it
+    #[serde(rename = "warning")]
+    Warning,
+    /// The failure is only reported
+    #[serde(rename = "notice")]
+    Notice,
+}
+
+impl Default for Severity {
+    fn default() -> Severity {
+        Severity::Error
+    }
+}
+
+/// The revision from which a pull request's configuration is loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigSource {
+    /// The branch into which the pull request will be merged
+    Base,
+    /// The repository's default branch
+    Default,
+    /// The head of the pull request itself
+    Head,
+}
+
+impl FromStr for ConfigSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ConfigSource> {
+        match s {
+            "base" => Ok(ConfigSource::Base),
+            "default" => Ok(ConfigSource::Default),
+            "head" => Ok(ConfigSource::Head),
+            _ => Err(format!("Unknown configuration source: {}", s).into()),
+        }
+    }
+}
+
+/// The location of a configuration file in another repository, written as
+/// "owner/repo:path/to/file.yaml", optionally followed by "@ref".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    pub owner: String,
+    pub repo: String,
+    pub path: String,
+    pub reference: Option<String>,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}:{}", self.owner, self.repo, self.path)?;
+        if let Some(ref reference) = self.reference {
+            write!(f, "@{}", reference)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Source> {
+        let (repository, location) = match s.find(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => return Err(format!("Missing path in {}", s).into()),
+        };
+        let (owner, repo) = match repository.find('/') {
+            Some(i) => (&repository[..i], &repository[i + 1..]),
+            None => return Err(format!("Missing repository owner in {}", s).into()),
+        };
+        let (path, reference) = match location.rfind('@') {
+            Some(i) => (&location[..i], Some(location[i + 1..].to_string())),
+            None => (location, None),
+        };
+
+        if owner.is_empty() || repo.is_empty() || path.is_empty() {
+            return Err(format!("Malformed configuration source: {}", s).into());
+        }
+
+        Ok(Source {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            path: path.to_string(),
+            reference,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Source {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Source, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn test_source() {
+        assert_eq!(
+            "coreos/.github:tailor/base.yaml".parse::<Source>().unwrap(),
+            Source {
+                owner: "coreos".into(),
+                repo: ".github".into(),
+                path: "tailor/base.yaml".into(),
+                reference: None,
+            }
+        );
+        assert_eq!(
+            "coreos/.github:tailor/base.yaml@v1"
+                .parse::<Source>()
+                .unwrap()
+                .reference,
+            Some("v1".into())
+        );
+        assert!("coreos/.github".parse::<Source>().is_err());
+        assert!(".github:tailor.yaml".parse::<Source>().is_err());
+        assert!("coreos/:tailor.yaml".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let parent: Config = serde_yaml::from_str(
+            r#"
+report: comment
+rules:
+  - { name: a, description: "", expression: "true" }
+  - { name: b, description: "", expression: "true" }
+  - { name: c, description: "", expression: "true" }
+"#,
+        ).unwrap();
+        let child: Config = serde_yaml::from_str(
+            r#"
+disable: [ b ]
+rules:
+  - { name: c, description: "", expression: "false" }
+  - { name: d, description: "", expression: "true" }
+"#,
+        ).unwrap();
+
+        let merged = child.merge(parent);
+        assert_eq!(
+            merged
+                .rules
+                .iter()
+                .map(|r| (r.name.as_str(), r.expression.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("a", "true"), ("c", "false"), ("d", "true")]
+        );
+        assert_eq!(merged.report, Some(Reporting::Comment));
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("release-*", "release-1.0"));
+        assert!(!glob("release-*", "master"));
+        assert!(glob("docs/*", "docs/README.md"));
+        assert!(!glob("docs/*", "docs/api/README.md"));
+        assert!(glob("docs/**", "docs/api/README.md"));
+        assert!(glob("**/*.md", "README.md"));
+        assert!(glob("**/*.md", "docs/api/README.md"));
+        assert!(!glob("**/*.md", "src/main.rs"));
+        assert!(!glob("**/main.rs", "src/xmain.rs"));
+        assert!(glob("src/?ain.rs", "src/main.rs"));
+        assert!(!glob("src?main.rs", "src/main.rs"));
+        assert!(glob("CHANGELOG.md", "CHANGELOG.md"));
+        assert!(!glob("CHANGELOG.md", "CHANGELOG.mdx"));
+    }
+
+    #[test]
+    fn test_condition() {
+        let target = Target {
+            base: "release-1.0".into(),
+            head: "fix-docs".into(),
+            files: vec!["docs/README.md".into(), "src/main.rs".into()],
+            labels: vec!["kind/bug".into()],
+            author: "crawford".into(),
+        };
+
+        assert!(Condition::default().applies(&target));
+        assert!(Condition {
+            base: vec!["master".into(), "release-*".into()],
+            paths: vec!["docs/**".into()],
+            labels: vec!["kind/bug".into()],
+            without_labels: vec!["skip-changelog".into()],
+            ..Default::default()
+        }.applies(&target));
+        assert!(!Condition {
+            base: vec!["master".into()],
+            ..Default::default()
+        }.applies(&target));
+        assert!(!Condition {
+            head: vec!["feature-*".into()],
+            ..Default::default()
+        }.applies(&target));
+        assert!(!Condition {
+            paths: vec!["assets/**".into()],
+            ..Default::default()
+        }.applies(&target));
+        assert!(!Condition {
+            without_labels: vec!["kind/bug".into()],
+            ..Default::default()
+        }.applies(&target));
+        assert!(!Condition {
+            authors: vec!["someone".into()],
+            ..Default::default()
+        }.applies(&target));
+        assert!(!Condition {
+            without_authors: vec!["crawford".into()],
+            ..Default::default()
+        }.applies(&target));
+    }
+
+    #[test]
+    fn test_authority() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+exemptions:
+  permission: write
+  users: [ crawford ]
+rules:
+  - { name: a, description: "", expression: "true" }
+  - name: b
+    description: ""
+    expression: "true"
+    exemptions:
+      teams: [ coreos/release ]
+"#,
+        ).unwrap();
+
+        assert_eq!(
+            config.authority("a").to_string(),
+            "write permission or being one of crawford"
+        );
+        assert_eq!(
+            config.authority("b").to_string(),
+            "membership in coreos/release"
+        );
+        assert_eq!(
+            config.authority("c").to_string(),
+            "write permission or being one of crawford"
+        );
+        assert_eq!(
+            Config::default().authority("a").to_string(),
+            "admin permission"
+        );
+    }
 }
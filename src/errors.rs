@@ -13,17 +13,27 @@
 // limitations under the License.
 
 use base64;
-use github_rs;
+use jsonwebtoken;
 use regex;
+use reqwest;
 use serde_json;
 use serde_yaml;
 
 error_chain!{
+    errors {
+        /// The pull request was pushed to after it was queued for evaluation
+        Superseded(head: String) {
+            description("pull request has a new head")
+            display("Pull request has a new head ({})", head)
+        }
+    }
+
     foreign_links {
         Base64(base64::DecodeError);
-        GithubError(github_rs::errors::Error);
         JsonError(serde_json::error::Error);
+        JwtError(jsonwebtoken::errors::Error);
         RegexError(regex::Error);
+        ReqwestError(reqwest::Error);
         YamlError(serde_yaml::Error);
     }
 }
@@ -53,6 +53,18 @@ pub enum Expr {
     Operation(Operation),
 }
 
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Numeral(n)
+    }
+}
+
 impl From<String> for Value {
     fn from(s: String) -> Self {
         Value::String(s)
@@ -74,6 +86,15 @@ impl From<DateTime<Utc>> for Value {
     }
 }
 
+impl From<Option<DateTime<Utc>>> for Value {
+    fn from(t: Option<DateTime<Utc>>) -> Self {
+        match t {
+            Some(t) => t.into(),
+            None => String::new().into(),
+        }
+    }
+}
+
 impl<T: Into<Value>> From<Vec<T>> for Value {
     fn from(v: Vec<T>) -> Self {
         Value::List(v.into_iter().map(|e| Expr::Value(e.into())).collect())
@@ -54,6 +54,301 @@ pub fn eval(expression: &str, input: &Value) -> Result<bool> {
     }
 }
 
+/// Why an expression evaluated as it did: the reasons, one per line (e.g. "commit abc1234: title
+/// length 63 is not < 51"), along with the list elements which decided the result (e.g. the
+/// commits with overly long titles).
+#[derive(Debug, Default, PartialEq)]
+pub struct Explanation {
+    pub reasons: Vec<String>,
+    pub elements: Vec<Value>,
+}
+
+/// Explains why the expression evaluated to false.
+pub fn explain(expression: &str, input: &Value) -> Result<Explanation> {
+    debug!("Explaining expression: {}", expression);
+    let mut explanation = Explanation::default();
+    explain_expr(
+        &ast::parse(expression).chain_err(|| "Failed to parse expression")?,
+        input,
+        false,
+        &mut explanation,
+    ).chain_err(|| "Failed to explain expression")?;
+    Ok(explanation)
+}
+
+/// Whether the expression looks up the named field of the elements of a list (e.g.
+/// `.commits all(.stats.total < 1000)` looks up `stats`), as opposed to a field of the input.
+pub fn uses_element_field(expression: &str, field: &str) -> Result<bool> {
+    fn uses(expr: &Expr, field: &str, nested: bool) -> bool {
+        let operation = match *expr {
+            Expr::Value(Value::List(ref list)) => {
+                return list.iter().any(|elem| uses(elem, field, nested))
+            }
+            Expr::Value(_) => return false,
+            Expr::Operation(ref operation) => operation,
+        };
+        match *operation {
+            Operation::All(ref list, ref condition)
+            | Operation::Any(ref list, ref condition)
+            | Operation::Filter(ref list, ref condition)
+            | Operation::Map(ref list, ref condition) => {
+                uses(list, field, nested) || uses(condition, field, true)
+            }
+            Operation::Equal(ref a, ref b)
+            | Operation::LessThan(ref a, ref b)
+            | Operation::GreaterThan(ref a, ref b)
+            | Operation::And(ref a, ref b)
+            | Operation::Or(ref a, ref b)
+            | Operation::Xor(ref a, ref b)
+            | Operation::Test(ref a, ref b) => uses(a, field, nested) || uses(b, field, nested),
+            Operation::Not(ref a) | Operation::Length(ref a) | Operation::Lines(ref a) => {
+                uses(a, field, nested)
+            }
+            Operation::Context(ref path) => nested && path.split('.').next() == Some(field),
+        }
+    }
+
+    Ok(uses(
+        &ast::parse(expression).chain_err(|| "Failed to parse expression")?,
+        field,
+        false,
+    ))
+}
+
+fn eval_bool(expr: &Expr, context: &Value) -> Result<bool> {
+    match eval_expr(expr.clone(), context)? {
+        Value::Boolean(b) => Ok(b),
+        value => {
+            warn!("Expression evaluated to {:?}", value);
+            Err("Invalid result".into())
+        }
+    }
+}
+
+/// The longest string which is quoted in full when explaining an expression.
+const MAX_EXPLAINED_STRING: usize = 50;
+
+/// Explains why the expression evaluated to the given result. List operations are explained in
+/// terms of the elements which decided the result and comparisons in terms of the values which
+/// were compared; anything else is simply restated.
+fn explain_expr(
+    expr: &Expr,
+    context: &Value,
+    result: bool,
+    explanation: &mut Explanation,
+) -> Result<()> {
+    match *expr {
+        Expr::Operation(Operation::Equal(ref a, ref b)) => {
+            compare(a, "=", b, context, result, explanation)?
+        }
+        Expr::Operation(Operation::LessThan(ref a, ref b)) => {
+            compare(a, "<", b, context, result, explanation)?
+        }
+        Expr::Operation(Operation::GreaterThan(ref a, ref b)) => {
+            compare(a, ">", b, context, result, explanation)?
+        }
+        Expr::Operation(Operation::Test(ref term, ref pattern)) => {
+            explanation.reasons.push(format!(
+                "{} {} {}",
+                operand(term, context)?,
+                if result { "matches" } else { "does not match" },
+                operand(pattern, context)?
+            ))
+        }
+        Expr::Operation(Operation::Not(ref a)) => explain_expr(a, context, !result, explanation)?,
+        // Only the first operand which decided the result was evaluated
+        Expr::Operation(Operation::And(ref a, ref b)) if !result => {
+            if eval_bool(a, context)? {
+                explain_expr(b, context, false, explanation)?
+            } else {
+                explain_expr(a, context, false, explanation)?
+            }
+        }
+        Expr::Operation(Operation::Or(ref a, ref b)) if result => {
+            if eval_bool(a, context)? {
+                explain_expr(a, context, true, explanation)?
+            } else {
+                explain_expr(b, context, true, explanation)?
+            }
+        }
+        Expr::Operation(Operation::And(ref a, ref b))
+        | Expr::Operation(Operation::Or(ref a, ref b)) => {
+            explain_expr(a, context, result, explanation)?;
+            explain_expr(b, context, result, explanation)?;
+        }
+        Expr::Operation(Operation::All(ref list, ref condition)) if !result => {
+            elements(list, condition, context, false, explanation)?
+        }
+        Expr::Operation(Operation::Any(ref list, ref condition)) => {
+            let reasons = explanation.reasons.len();
+            elements(list, condition, context, result, explanation)?;
+            if explanation.reasons.len() == reasons {
+                explanation
+                    .reasons
+                    .push(format!("{} is empty", describe(list)));
+            }
+        }
+        _ => explanation
+            .reasons
+            .push(format!("{} is {}", operand(expr, context)?, result)),
+    }
+    Ok(())
+}
+
+/// Explains each of the elements of the list for which the condition evaluated to the result.
+fn elements(
+    list: &Expr,
+    condition: &Expr,
+    context: &Value,
+    result: bool,
+    explanation: &mut Explanation,
+) -> Result<()> {
+    for (i, elem) in expr!(list.clone(), context, Value::List)
+        .into_iter()
+        .enumerate()
+    {
+        let elem = eval_expr(elem, context)?;
+        if eval_bool(condition, &elem)? == result {
+            let label = label(list, i, &elem);
+            let mut reasons = Explanation::default();
+            explain_expr(condition, &elem, result, &mut reasons)?;
+            explanation.reasons.extend(
+                reasons
+                    .reasons
+                    .into_iter()
+                    .map(|reason| format!("{}: {}", label, reason)),
+            );
+            explanation.elements.push(elem);
+            explanation.elements.extend(reasons.elements);
+        }
+    }
+    Ok(())
+}
+
+/// Explains a comparison, along with the elements picked out by any filter which was compared
+/// (e.g. the commits counted by `.commits filter(.title test "WIP") length = 0`).
+fn compare(
+    a: &Expr,
+    op: &str,
+    b: &Expr,
+    context: &Value,
+    result: bool,
+    explanation: &mut Explanation,
+) -> Result<()> {
+    explanation.reasons.push(format!(
+        "{} is {}{} {}",
+        operand(a, context)?,
+        if result { "" } else { "not " },
+        op,
+        operand(b, context)?
+    ));
+    for side in &[a, b] {
+        if let Some((list, condition)) = filter(side) {
+            elements(list, condition, context, true, explanation)?;
+        }
+    }
+    Ok(())
+}
+
+fn filter(expr: &Expr) -> Option<(&Expr, &Expr)> {
+    match *expr {
+        Expr::Operation(Operation::Filter(ref list, ref condition)) => Some((list, condition)),
+        Expr::Operation(Operation::Length(ref a)) => filter(a),
+        _ => None,
+    }
+}
+
+/// Names the element of the list, preferring something recognizable (e.g. a commit's SHA) over its
+/// position.
+fn label(list: &Expr, index: usize, elem: &Value) -> String {
+    if let Value::Dictionary(ref map) = *elem {
+        let field = |key: &str| match map.get(key) {
+            Some(&Value::String(ref s)) => Some(s.clone()),
+            _ => None,
+        };
+        if let Some(sha) = field("sha") {
+            return format!("commit {}", &sha[..sha.len().min(7)]);
+        }
+        if let Some(filename) = field("filename") {
+            return format!("file {}", filename);
+        }
+        if let Some(login) = field("login") {
+            return format!("user {}", login);
+        }
+    }
+    format!("item {} of {}", index + 1, describe(list))
+}
+
+/// Describes the operand along with its value (e.g. `title length 63`).
+fn operand(expr: &Expr, context: &Value) -> Result<String> {
+    let value = display(&eval_expr(expr.clone(), context)?);
+    let description = match *expr {
+        Expr::Value(_) => String::new(),
+        _ => describe(expr),
+    };
+    Ok(if description.is_empty() {
+        value
+    } else {
+        format!("{} {}", description, value)
+    })
+}
+
+/// Renders the expression roughly as it was written, leaving off the leading dots of the context
+/// (e.g. `title length`).
+fn describe(expr: &Expr) -> String {
+    fn argument(expr: &Expr) -> String {
+        match *expr {
+            Expr::Operation(Operation::Context(ref path)) if path.is_empty() => ".".to_string(),
+            Expr::Value(_) | Expr::Operation(Operation::Context(_)) => describe(expr),
+            _ => format!("({})", describe(expr)),
+        }
+    }
+    let infix = |a: &Expr, op: &str, b: &Expr| {
+        format!("{} {} {}", describe(a), op, argument(b))
+            .trim()
+            .to_string()
+    };
+    let postfix = |a: &Expr, op: &str| format!("{} {}", describe(a), op).trim().to_string();
+
+    match *expr {
+        Expr::Value(ref value) => display(value),
+        Expr::Operation(ref operation) => match *operation {
+            Operation::Equal(ref a, ref b) => infix(a, "=", b),
+            Operation::LessThan(ref a, ref b) => infix(a, "<", b),
+            Operation::GreaterThan(ref a, ref b) => infix(a, ">", b),
+            Operation::And(ref a, ref b) => infix(a, "and", b),
+            Operation::Or(ref a, ref b) => infix(a, "or", b),
+            Operation::Xor(ref a, ref b) => infix(a, "xor", b),
+            Operation::Not(ref a) => postfix(a, "not"),
+            Operation::All(ref a, ref b) => infix(a, "all", b),
+            Operation::Any(ref a, ref b) => infix(a, "any", b),
+            Operation::Filter(ref a, ref b) => infix(a, "filter", b),
+            Operation::Map(ref a, ref b) => infix(a, "map", b),
+            Operation::Length(ref a) => postfix(a, "length"),
+            Operation::Test(ref a, ref b) => infix(a, "test", b),
+            Operation::Lines(ref a) => postfix(a, "lines"),
+            Operation::Context(ref path) => path.clone(),
+        },
+    }
+}
+
+fn display(value: &Value) -> String {
+    match *value {
+        Value::Numeral(n) => n.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::String(ref s) if s.chars().count() > MAX_EXPLAINED_STRING => format!(
+            "{:?}...",
+            s.chars().take(MAX_EXPLAINED_STRING).collect::<String>()
+        ),
+        Value::String(ref s) => format!("{:?}", s),
+        Value::List(ref list) => format!(
+            "[{}]",
+            list.iter().map(describe).collect::<Vec<_>>().join(" ")
+        ),
+        Value::Dictionary(_) => "{...}".to_string(),
+    }
+}
+
 fn eval_expr(expr: Expr, context: &Value) -> Result<Value> {
     trace!("Evaluating expression: {:?}", expr);
     let result = match expr {
@@ -194,6 +489,77 @@ mod test {
         //assert_eq!(eval_pr("true length").unwrap(), Err(String::from("Invalid type")));
     }
 
+    #[test]
+    fn test_uses_element_field() {
+        assert!(uses_element_field(".commits all(.stats.total < 1000)", "stats").unwrap());
+        assert!(uses_element_field(".commits map(.files) all(. length < 10)", "files").unwrap());
+        assert!(uses_element_field(".commits all(.files length < 10)", "files").unwrap());
+        assert!(!uses_element_field(".files any(.filename = \"CHANGELOG.md\")", "files").unwrap());
+        assert!(!uses_element_field(".commits all(.title length < 51)", "files").unwrap());
+    }
+
+    #[test]
+    fn test_explain() {
+        let commit = |sha: &str, title: &str| {
+            let mut map = HashMap::new();
+            map.insert(String::from("sha"), Value::String(sha.into()));
+            map.insert(String::from("title"), Value::String(title.into()));
+            Expr::Value(Value::Dictionary(map))
+        };
+        let mut map = HashMap::new();
+        map.insert(
+            String::from("commits"),
+            Value::List(vec![
+                commit("0123456789", "WIP: short"),
+                commit("abcdef0123", "a title which is too long"),
+            ]),
+        );
+        let input = Value::Dictionary(map);
+
+        let reasons = |expression| explain(expression, &input).unwrap().reasons;
+
+        assert_eq!(
+            reasons(".commits all(.title length < 20)"),
+            vec!["commit abcdef0: title length 25 is not < 20"]
+        );
+        assert_eq!(
+            reasons(r#".commits filter(.title test "WIP") length = 0"#),
+            vec![
+                r#"commits filter (title test "WIP") length 1 is not = 0"#,
+                r#"commit 0123456: title "WIP: short" matches "WIP""#,
+            ]
+        );
+        assert_eq!(
+            reasons(r#".commits any(.title test "WIP") not"#),
+            vec![r#"commit 0123456: title "WIP: short" matches "WIP""#]
+        );
+        assert_eq!(
+            reasons("[1 5 2] all(. < 3) and true"),
+            vec!["item 2 of [1 5 2]: 5 is not < 3"]
+        );
+        assert_eq!(reasons("[] any ."), vec!["[] is empty"]);
+    }
+
+    #[test]
+    fn test_explain_elements() {
+        let input = Value::Dictionary(HashMap::new());
+        let elements = |expression| explain(expression, &input).unwrap().elements;
+
+        assert_eq!(
+            elements("[1 2 3] all(. < 2)"),
+            vec![Value::Numeral(2), Value::Numeral(3)]
+        );
+        assert_eq!(
+            elements("[1 2 3] all(. < 2) and true"),
+            vec![Value::Numeral(2), Value::Numeral(3)]
+        );
+        assert_eq!(
+            elements("[1 2 3] any(. < 2) not"),
+            vec![Value::Numeral(1)]
+        );
+        assert_eq!(elements("1 = 2"), vec![]);
+    }
+
     fn eval_pr(expression: &str) -> Result<bool> {
         let mut map = HashMap::new();
         map.insert(
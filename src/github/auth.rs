@@ -0,0 +1,232 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use base64;
+use chrono::prelude::*;
+use chrono::Duration;
+use errors::*;
+use github;
+use github::client::Github;
+use github::types;
+use jsonwebtoken::{self, Algorithm, Header};
+use reqwest::{self, header};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use sync;
+
+/// Installation tokens are refreshed once they are this close to expiring so that a token never
+/// expires in the middle of an evaluation.
+const REFRESH_MARGIN_MINUTES: i64 = 5;
+
+/// The means by which Tailor authenticates with GitHub.
+pub enum Credentials {
+    /// A single personal access token, used for every repository.
+    Token(String),
+    /// A GitHub App, which mints a separate access token for each installation.
+    App { id: u64, key: Vec<u8> },
+}
+
+impl Credentials {
+    /// Reads a GitHub App's PEM-encoded private key from the given path.
+    pub fn app(id: u64, key_path: &Path) -> Result<Credentials> {
+        let mut pem = String::new();
+        File::open(key_path)
+            .and_then(|mut file| file.read_to_string(&mut pem))
+            .chain_err(|| format!("Failed to read private key {}", key_path.display()))?;
+
+        let key = base64::decode_config(
+            &pem.lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect::<String>(),
+            base64::MIME,
+        ).chain_err(|| "Failed to decode private key")?;
+
+        Ok(Credentials::App { id, key })
+    }
+}
+
+#[derive(Serialize)]
+struct Claims {
+    iat: i64,
+    exp: i64,
+    iss: u64,
+}
+
+#[derive(Deserialize)]
+struct App {
+    slug: String,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+struct Installation {
+    client: Arc<Github>,
+    expires_at: DateTime<Utc>,
+}
+
+/// Hands out GitHub clients, authenticated either with the personal access token or with an
+/// access token for the installation which sent the event. Installation tokens are cached until
+/// shortly before they expire. Each installation is locked separately while its token is minted,
+/// so that threads working on other installations aren't held up.
+pub struct Authenticator {
+    credentials: Credentials,
+    api_url: String,
+    installation_urls: HashMap<u64, String>,
+    client: Option<Arc<Github>>,
+    installations: Mutex<HashMap<u64, Arc<Mutex<Option<Installation>>>>>,
+}
+
+impl Authenticator {
+    /// Creates an authenticator whose clients send their requests to the given API URL, except for
+    /// the installations with their own API URL (e.g. on a GitHub Enterprise Server).
+    pub fn new(
+        credentials: Credentials,
+        api_url: String,
+        installation_urls: HashMap<u64, String>,
+    ) -> Result<Authenticator> {
+        let client = match credentials {
+            Credentials::Token(ref token) => Some(Arc::new(Github::new(&api_url, token)?)),
+            Credentials::App { .. } => None,
+        };
+
+        Ok(Authenticator {
+            credentials,
+            api_url,
+            installation_urls,
+            client,
+            installations: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Looks up the login with which Tailor comments: the token's user, or the App's bot user.
+    pub fn login(&self) -> Result<String> {
+        match self.credentials {
+            Credentials::Token(_) => {
+                let client = self.client.as_ref().expect("token client");
+                github::retry(|| client.get("user"))
+                    .try_execute::<types::User>()
+                    .map(|user| user.login)
+                    .chain_err(|| "Failed to fetch authenticated user")
+            }
+            Credentials::App { id, ref key } => {
+                fetch_app(&self.api_url, id, key).map(|app| format!("{}[bot]", app.slug))
+            }
+        }
+    }
+
+    pub fn client(&self, installation: Option<u64>) -> Result<Arc<Github>> {
+        let (app_id, key) = match self.credentials {
+            Credentials::Token(_) => {
+                return Ok(self.client.clone().expect("token client"));
+            }
+            Credentials::App { id, ref key } => (id, key),
+        };
+
+        let installation =
+            installation.ok_or_else(|| Error::from("Event did not include an installation"))?;
+
+        // Only the installation's entry stays locked while its token is minted
+        let entry = sync::lock(&self.installations)
+            .entry(installation)
+            .or_insert_with(Default::default)
+            .clone();
+        let mut cached = sync::lock(&entry);
+
+        let expired = match *cached {
+            Some(ref cached) => {
+                cached.expires_at - Duration::minutes(REFRESH_MARGIN_MINUTES) < Utc::now()
+            }
+            None => true,
+        };
+
+        if expired {
+            let api_url = self.installation_urls
+                .get(&installation)
+                .unwrap_or(&self.api_url);
+            debug!(
+                "Minting access token for installation {} from {}",
+                installation, api_url
+            );
+            let token = create_access_token(api_url, app_id, key, installation)
+                .chain_err(|| format!("Failed to authenticate installation {}", installation))?;
+            *cached = Some(Installation {
+                client: Arc::new(Github::new(api_url, &token.token)?),
+                expires_at: token.expires_at,
+            });
+        }
+
+        Ok(cached.as_ref().expect("installation client").client.clone())
+    }
+}
+
+fn create_jwt(app_id: u64, key: &[u8]) -> Result<String> {
+    let now = Utc::now();
+    let claims = Claims {
+        // Backdated to allow for clock drift between us and GitHub
+        iat: (now - Duration::seconds(60)).timestamp(),
+        exp: (now + Duration::minutes(9)).timestamp(),
+        iss: app_id,
+    };
+
+    jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, key)
+        .chain_err(|| "Failed to sign token")
+}
+
+fn fetch_app(api_url: &str, app_id: u64, key: &[u8]) -> Result<App> {
+    let mut response = reqwest::Client::new()
+        .get(&format!("{}/app", api_url.trim_end_matches('/')))
+        .header(header::AUTHORIZATION, format!("Bearer {}", create_jwt(app_id, key)?))
+        .header(header::ACCEPT, "application/vnd.github.machine-man-preview+json")
+        .header(header::USER_AGENT, "tailor")
+        .send()
+        .chain_err(|| "Failed to request app")?;
+
+    if !response.status().is_success() {
+        return Err(format!("Received {} while requesting app", response.status()).into());
+    }
+
+    Ok(response.json()?)
+}
+
+fn create_access_token(
+    api_url: &str,
+    app_id: u64,
+    key: &[u8],
+    installation: u64,
+) -> Result<AccessToken> {
+    let mut response = reqwest::Client::new()
+        .post(&format!(
+            "{}/app/installations/{}/access_tokens",
+            api_url.trim_end_matches('/'),
+            installation
+        ))
+        .header(header::AUTHORIZATION, format!("Bearer {}", create_jwt(app_id, key)?))
+        .header(header::ACCEPT, "application/vnd.github.machine-man-preview+json")
+        .header(header::USER_AGENT, "tailor")
+        .send()
+        .chain_err(|| "Failed to request access token")?;
+
+    if !response.status().is_success() {
+        return Err(format!("Received {} while requesting access token", response.status()).into());
+    }
+
+    Ok(response.json()?)
+}
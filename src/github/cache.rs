@@ -0,0 +1,266 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use github::types::Permission;
+use iron;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sync;
+
+/// Remembers collaborator permissions and team memberships across evaluations, so that busy pull
+/// requests don't look up the same users over and over. Entries expire after the TTL, and are
+/// dropped early when GitHub reports a change (see `invalidate_collaborator` and
+/// `invalidate_member`). Clones share the same entries.
+#[derive(Clone)]
+pub struct PermissionCache {
+    ttl: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Keyed by (owner, repo, login)
+    permissions: HashMap<(String, String, String), Entry<Permission>>,
+    /// Keyed by (org, team slug, login)
+    memberships: HashMap<(String, String, String), Entry<bool>>,
+    stats: Stats,
+}
+
+struct Entry<T> {
+    value: T,
+    created: Instant,
+}
+
+/// The number of lookups which were (and weren't) answered by the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} hits, {} misses", self.hits, self.misses)
+    }
+}
+
+impl PermissionCache {
+    pub fn new(ttl: Duration) -> PermissionCache {
+        PermissionCache {
+            ttl,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    pub fn permission(&self, owner: &str, repo: &str, login: &str) -> Option<Permission> {
+        let mut guard = sync::lock(&self.state);
+        let state = &mut *guard;
+        let permission = lookup(&mut state.permissions, &key(owner, repo, login), self.ttl);
+        state.stats.record(permission.is_some());
+        permission
+    }
+
+    pub fn insert_permission(&self, owner: &str, repo: &str, login: &str, permission: Permission) {
+        sync::lock(&self.state)
+            .permissions
+            .insert(key(owner, repo, login), Entry::new(permission));
+    }
+
+    pub fn membership(&self, org: &str, team: &str, login: &str) -> Option<bool> {
+        let mut guard = sync::lock(&self.state);
+        let state = &mut *guard;
+        let member = lookup(&mut state.memberships, &key(org, team, login), self.ttl);
+        state.stats.record(member.is_some());
+        member
+    }
+
+    pub fn insert_membership(&self, org: &str, team: &str, login: &str, member: bool) {
+        sync::lock(&self.state)
+            .memberships
+            .insert(key(org, team, login), Entry::new(member));
+    }
+
+    /// Forgets the user's permission on the repository (e.g. after a `member` event).
+    pub fn invalidate_collaborator(&self, owner: &str, repo: &str, login: &str) {
+        debug!("Invalidating cached permission of {} on {}/{}", login, owner, repo);
+        sync::lock(&self.state).permissions.remove(&key(owner, repo, login));
+    }
+
+    /// Forgets the user's membership in the team (e.g. after a `membership` event). Since teams
+    /// confer permissions on repositories, all of the user's cached permissions are forgotten too.
+    pub fn invalidate_member(&self, org: &str, team: &str, login: &str) {
+        debug!("Invalidating cached membership of {} in {}/{}", login, org, team);
+        let mut state = sync::lock(&self.state);
+        state.memberships.remove(&key(org, team, login));
+        state
+            .permissions
+            .retain(|&(_, _, ref user), _| user != login);
+    }
+
+    pub fn stats(&self) -> Stats {
+        sync::lock(&self.state).stats
+    }
+}
+
+/// Remembers the reactions which have been added to comments, so that each command is only
+/// acknowledged when it is first processed rather than on every evaluation. Only the most recent
+/// reactions are kept; forgetting one just means that it is added again. Clones share the same
+/// entries.
+#[derive(Clone)]
+pub struct ReactionCache {
+    capacity: usize,
+    state: Arc<Mutex<Reactions>>,
+}
+
+#[derive(Default)]
+struct Reactions {
+    /// Keyed by (comment ID, reaction)
+    reacted: HashSet<(u64, String)>,
+    order: VecDeque<(u64, String)>,
+}
+
+impl ReactionCache {
+    pub fn new(capacity: usize) -> ReactionCache {
+        ReactionCache {
+            capacity,
+            state: Arc::new(Mutex::new(Reactions::default())),
+        }
+    }
+
+    /// Whether the reaction has already been added to the comment.
+    pub fn contains(&self, comment: u64, content: &str) -> bool {
+        sync::lock(&self.state).reacted.contains(&(comment, content.to_string()))
+    }
+
+    /// Records that the reaction was added to the comment, forgetting the oldest reaction if the
+    /// cache is full.
+    pub fn insert(&self, comment: u64, content: &str) {
+        let key = (comment, content.to_string());
+        let mut state = sync::lock(&self.state);
+        if !state.reacted.insert(key.clone()) {
+            return;
+        }
+        state.order.push_back(key);
+        while state.order.len() > self.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                state.reacted.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl iron::typemap::Key for PermissionCache {
+    type Value = PermissionCache;
+}
+
+impl<T> Entry<T> {
+    fn new(value: T) -> Entry<T> {
+        Entry {
+            value,
+            created: Instant::now(),
+        }
+    }
+}
+
+impl Stats {
+    fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
+
+fn key(a: &str, b: &str, c: &str) -> (String, String, String) {
+    (a.to_string(), b.to_string(), c.to_string())
+}
+
+/// Finds the unexpired value for the key, removing it if it has expired.
+fn lookup<K: Eq + Hash, T: Copy>(
+    entries: &mut HashMap<K, Entry<T>>,
+    key: &K,
+    ttl: Duration,
+) -> Option<T> {
+    match entries.get(key).map(|entry| (entry.value, entry.created.elapsed() < ttl)) {
+        Some((value, true)) => Some(value),
+        Some((_, false)) => {
+            entries.remove(key);
+            None
+        }
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let cache = PermissionCache::new(Duration::from_secs(60));
+        assert_eq!(cache.permission("coreos", "tailor", "crawford"), None);
+
+        cache.insert_permission("coreos", "tailor", "crawford", Permission::Admin);
+        cache.insert_membership("coreos", "maintainers", "crawford", true);
+        assert_eq!(
+            cache.permission("coreos", "tailor", "crawford"),
+            Some(Permission::Admin)
+        );
+        assert_eq!(cache.permission("coreos", "ignition", "crawford"), None);
+        assert_eq!(
+            cache.membership("coreos", "maintainers", "crawford"),
+            Some(true)
+        );
+
+        cache.invalidate_collaborator("coreos", "tailor", "crawford");
+        assert_eq!(cache.permission("coreos", "tailor", "crawford"), None);
+
+        cache.insert_permission("coreos", "tailor", "crawford", Permission::Admin);
+        cache.invalidate_member("coreos", "maintainers", "crawford");
+        assert_eq!(cache.permission("coreos", "tailor", "crawford"), None);
+        assert_eq!(cache.membership("coreos", "maintainers", "crawford"), None);
+
+        assert_eq!(cache.stats(), Stats { hits: 2, misses: 5 });
+    }
+
+    #[test]
+    fn test_expiry() {
+        let cache = PermissionCache::new(Duration::from_secs(0));
+        cache.insert_permission("coreos", "tailor", "crawford", Permission::Admin);
+        assert_eq!(cache.permission("coreos", "tailor", "crawford"), None);
+        assert!(sync::lock(&cache.state).permissions.is_empty());
+    }
+
+    #[test]
+    fn test_reactions() {
+        let cache = ReactionCache::new(2);
+        assert!(!cache.contains(1, "+1"));
+
+        cache.insert(1, "+1");
+        cache.insert(1, "+1");
+        cache.insert(2, "confused");
+        assert!(cache.contains(1, "+1"));
+        assert!(!cache.contains(1, "-1"));
+        assert!(cache.contains(2, "confused"));
+
+        cache.insert(3, "+1");
+        assert!(!cache.contains(1, "+1"));
+        assert!(cache.contains(2, "confused"));
+        assert!(cache.contains(3, "+1"));
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use errors::*;
+use reqwest::header::{self, HeaderMap};
+use reqwest::{self, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+
+/// A client for the GitHub REST API, authenticated with an access token. Every request is sent
+/// relative to the client's API URL, so the same client works with github.com and GitHub
+/// Enterprise Server alike.
+pub struct Github {
+    http: reqwest::Client,
+    api_url: String,
+    token: String,
+}
+
+/// A request which has been built but not yet sent.
+pub struct Request {
+    builder: reqwest::RequestBuilder,
+}
+
+pub trait Executor {
+    /// Sends the request, returning the headers, status, and (if there is one) the body of the
+    /// response, whether or not the request was successful.
+    fn execute<T: DeserializeOwned>(self) -> Result<(HeaderMap, StatusCode, Option<T>)>;
+}
+
+impl Github {
+    pub fn new(api_url: &str, token: &str) -> Result<Github> {
+        Ok(Github {
+            http: reqwest::Client::builder()
+                .build()
+                .chain_err(|| "Failed to create HTTP client")?,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        })
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// Starts a GET request of the endpoint (e.g. "repos/coreos/tailor/pulls/1").
+    pub fn get(&self, endpoint: &str) -> Request {
+        self.request(self.http.get(&self.url(endpoint)))
+    }
+
+    /// Starts a POST request of the endpoint, with the body serialized as JSON.
+    pub fn post<T: Serialize>(&self, endpoint: &str, body: T) -> Request {
+        self.request(self.http.post(&self.url(endpoint)).json(&body))
+    }
+
+    /// Starts a PATCH request of the endpoint, with the body serialized as JSON.
+    pub fn patch<T: Serialize>(&self, endpoint: &str, body: T) -> Request {
+        self.request(self.http.patch(&self.url(endpoint)).json(&body))
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!("{}/{}", self.api_url, endpoint.trim_start_matches('/'))
+    }
+
+    fn request(&self, builder: reqwest::RequestBuilder) -> Request {
+        Request {
+            builder: builder
+                .header(header::AUTHORIZATION, format!("token {}", self.token))
+                .header(header::ACCEPT, "application/vnd.github.v3+json")
+                .header(header::USER_AGENT, "tailor"),
+        }
+    }
+}
+
+impl Executor for Request {
+    fn execute<T: DeserializeOwned>(self) -> Result<(HeaderMap, StatusCode, Option<T>)> {
+        let mut response = self.builder.send()?;
+        let body = response.text()?;
+        let body = if body.trim().is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(&body)?)
+        };
+        Ok((response.headers().clone(), response.status(), body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use github::TryExecute;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves a single canned response, returning the request line which it received.
+    fn mock_server(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let address = listener.local_addr().expect("mock server address");
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept connection");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+            let mut request = String::new();
+            reader.read_line(&mut request).expect("read request line");
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("read header");
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            ).expect("write response");
+            request.trim().to_string()
+        });
+        (format!("http://{}/api/v3/", address), handle)
+    }
+
+    #[test]
+    fn test_api_url() {
+        let (api_url, server) = mock_server(r#"{"login": "crawford"}"#);
+        let client = Github::new(&api_url, "secret").unwrap();
+        let user: ::github::types::User = client.get("/users/crawford").try_execute().unwrap();
+
+        assert_eq!(user.login, "crawford");
+        assert_eq!(server.join().unwrap(), "GET /api/v3/users/crawford HTTP/1.1");
+    }
+}
@@ -12,43 +12,391 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod auth;
+pub mod cache;
+pub mod client;
+pub mod signature;
 pub mod types;
 pub mod validate;
 
+use chrono::{DateTime, Utc};
 use errors::*;
+use github::client::{Executor, Github, Request};
 use github::types::ErrorResponse;
-use github_rs::client::Executor;
-use github_rs::StatusCode;
+use reqwest::header::HeaderMap as Headers;
+use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
 use serde_json;
+use std::str;
+use std::thread;
+use std::time::Duration;
+
+/// The API URL of github.com. GitHub Enterprise Server serves its API from
+/// "https://<hostname>/api/v3" instead.
+pub const API_URL: &str = "https://api.github.com";
+
+/// The number of items requested in each page of a listing (the maximum allowed by GitHub).
+const PAGE_SIZE: usize = 100;
+
+/// The number of times a request is attempted before giving up on it.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// The delay before the first retry, which is doubled before each subsequent retry.
+const INITIAL_BACKOFF_SECS: u64 = 1;
+
+/// The longest that a request will be delayed (e.g. waiting for a rate limit to reset). The delay
+/// holds up the thread which made the request, so requests which would need to wait longer fail
+/// instead.
+const MAX_BACKOFF_SECS: u64 = 60;
 
 pub trait TryExecute: Executor {
     fn try_execute<T: DeserializeOwned>(self) -> Result<T>
     where
         Self: Sized,
     {
-        match self.execute::<serde_json::Value>() {
-            Ok((_, StatusCode::Ok, Some(response)))
-            | Ok((_, StatusCode::Created, Some(response))) => {
-                serde_json::from_value(response).chain_err(|| "Failed to parse response")
+        self.try_execute_with_headers().map(|(_, response)| response)
+    }
+
+    fn try_execute_with_headers<T: DeserializeOwned>(self) -> Result<(Headers, T)>
+    where
+        Self: Sized,
+    {
+        parse_response(self.execute::<serde_json::Value>().chain_err(|| "Failed to execute request"))
+    }
+}
+
+/// A request which is retried when GitHub fails in a way that is likely to be temporary (e.g. a
+/// 502 or a rate limit). Since the request may be sent more than once, only idempotent requests
+/// should be retried.
+pub struct Retry<F> {
+    request: F,
+    max_backoff: u64,
+}
+
+/// Retries the request built by the closure, e.g.
+/// `github::retry(|| client.get("rate_limit")).try_execute()`.
+pub fn retry<F, E>(request: F) -> Retry<F>
+where
+    F: FnMut() -> E,
+    E: Executor,
+{
+    Retry {
+        request,
+        max_backoff: MAX_BACKOFF_SECS,
+    }
+}
+
+impl<F, E> Retry<F>
+where
+    F: FnMut() -> E,
+    E: Executor,
+{
+    /// Gives up on the request rather than waiting more than the given number of seconds before
+    /// retrying it, for callers which would hold up other requests while they wait.
+    pub fn max_backoff(mut self, secs: u64) -> Retry<F> {
+        self.max_backoff = secs.min(MAX_BACKOFF_SECS);
+        self
+    }
+
+    pub fn try_execute<T: DeserializeOwned>(self) -> Result<T> {
+        self.try_execute_with_headers().map(|(_, response)| response)
+    }
+
+    pub fn try_execute_with_headers<T: DeserializeOwned>(self) -> Result<(Headers, T)> {
+        parse_response(self.send())
+    }
+
+    /// Like `Executor::execute`, except that the body of an unsuccessful response is discarded if
+    /// it doesn't match the expected type.
+    pub fn execute<T: DeserializeOwned>(self) -> Result<(Headers, StatusCode, Option<T>)> {
+        let (headers, status, response) = self.send()?;
+        let response = match response {
+            Some(response) => match serde_json::from_value(response) {
+                Ok(response) => Some(response),
+                Err(_) if !status.is_success() => None,
+                Err(err) => return Err(err).chain_err(|| "Failed to parse response"),
+            },
+            None => None,
+        };
+        Ok((headers, status, response))
+    }
+
+    fn send(mut self) -> Result<(Headers, StatusCode, Option<serde_json::Value>)> {
+        let mut attempt = 1;
+        loop {
+            let result = (self.request)()
+                .execute::<serde_json::Value>()
+                .chain_err(|| "Failed to execute request");
+
+            let delay = match result {
+                Ok((ref headers, status, ref body)) => {
+                    let limit = RateLimit {
+                        secondary: body.as_ref().map_or(false, is_secondary_rate_limit),
+                        ..RateLimit::from(headers)
+                    };
+                    backoff(Some(status), &limit, Utc::now(), attempt)
+                }
+                Err(_) => backoff(None, &RateLimit::default(), Utc::now(), attempt),
+            };
+
+            match delay {
+                Some(delay) if delay.as_secs() > self.max_backoff => {
+                    warn!("GitHub asked us to wait {} seconds; giving up", delay.as_secs());
+                    return result;
+                }
+                Some(delay) => {
+                    match result {
+                        Ok((_, status, _)) => warn!(
+                            "Received {} from GitHub; retrying in {} seconds",
+                            status,
+                            delay.as_secs()
+                        ),
+                        Err(err) => warn!(
+                            "Failed to reach GitHub ({}); retrying in {} seconds",
+                            err,
+                            delay.as_secs()
+                        ),
+                    }
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return result,
             }
-            Ok((_, _, Some(response))) => serde_json::from_value::<ErrorResponse>(response)
-                .chain_err(|| "Failed to parse error response")
-                .and_then(|error| {
-                    debug!("Failed to complete request: {:?}", error);
-                    Err(error.message.into())
-                }),
-            Ok((_, _, None)) => Err("Received error response from github with no message".into()),
-            Err(err) => Err(err).chain_err(|| "Failed to execute request"),
-        }.or_else(|err| {
-            error!("Failed to complete request: {}", err);
-            Err(err)
+        }
+    }
+}
+
+/// The rate limit information which GitHub includes in its responses.
+#[derive(Debug, Default)]
+struct RateLimit {
+    /// The number of requests remaining in the current window
+    remaining: Option<u64>,
+    /// When the current window ends, in seconds since the epoch
+    reset: Option<i64>,
+    /// How long to wait before making another request (e.g. after hitting a secondary rate limit)
+    retry_after: Option<u64>,
+    /// Whether the response says that a secondary rate limit was hit, which GitHub doesn't always
+    /// accompany with Retry-After
+    secondary: bool,
+}
+
+impl<'a> From<&'a Headers> for RateLimit {
+    fn from(headers: &'a Headers) -> RateLimit {
+        fn header<T: str::FromStr>(headers: &Headers, name: &str) -> Option<T> {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+        }
+
+        RateLimit {
+            remaining: header(headers, "X-RateLimit-Remaining"),
+            reset: header(headers, "X-RateLimit-Reset"),
+            retry_after: header(headers, "Retry-After"),
+            secondary: false,
+        }
+    }
+}
+
+/// Whether the body of an error response says that a secondary rate limit (formerly known as abuse
+/// detection) was hit.
+fn is_secondary_rate_limit(body: &serde_json::Value) -> bool {
+    body.get("message")
+        .and_then(|message| message.as_str())
+        .map_or(false, |message| {
+            let message = message.to_lowercase();
+            message.contains("secondary rate limit") || message.contains("abuse detection")
         })
+}
+
+/// How long to wait before retrying a request, or None if it shouldn't be retried. Requests which
+/// couldn't be sent at all (no status) are retried, as are server errors and rate limits.
+fn backoff(
+    status: Option<StatusCode>,
+    limit: &RateLimit,
+    now: DateTime<Utc>,
+    attempt: u32,
+) -> Option<Duration> {
+    if attempt >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    let exponential = INITIAL_BACKOFF_SECS << (attempt - 1);
+    let secs = match status {
+        None => exponential,
+        Some(status) if status.is_server_error() => exponential,
+        Some(StatusCode::FORBIDDEN) | Some(StatusCode::TOO_MANY_REQUESTS) => {
+            match (limit.retry_after, limit.remaining, limit.reset) {
+                (Some(retry_after), _, _) => retry_after,
+                (None, Some(0), Some(reset)) => (reset - now.timestamp()).max(0) as u64 + 1,
+                // Secondary rate limits aren't always accompanied by Retry-After
+                (None, _, _) if limit.secondary => exponential,
+                (None, _, _) if status == Some(StatusCode::TOO_MANY_REQUESTS) => exponential,
+                // Otherwise, it's a genuine lack of permission
+                (None, _, _) => return None,
+            }
+        }
+        Some(_) => return None,
+    };
+
+    if secs > MAX_BACKOFF_SECS {
+        warn!("GitHub asked us to wait {} seconds; giving up", secs);
+        return None;
     }
+    Some(Duration::from_secs(secs))
+}
+
+/// Interprets the response to a request, converting unsuccessful responses into errors.
+fn parse_response<T: DeserializeOwned>(
+    result: Result<(Headers, StatusCode, Option<serde_json::Value>)>,
+) -> Result<(Headers, T)> {
+    match result {
+        Ok((headers, StatusCode::OK, Some(response)))
+        | Ok((headers, StatusCode::CREATED, Some(response))) => serde_json::from_value(response)
+            .map(|response| (headers, response))
+            .chain_err(|| "Failed to parse response"),
+        Ok((_, _, Some(response))) => serde_json::from_value::<ErrorResponse>(response)
+            .chain_err(|| "Failed to parse error response")
+            .and_then(|error| {
+                debug!("Failed to complete request: {:?}", error);
+                Err(error.message.into())
+            }),
+        Ok((_, _, None)) => Err("Received error response from github with no message".into()),
+        Err(err) => Err(err),
+    }.or_else(|err| {
+        error!("Failed to complete request: {}", err);
+        Err(err)
+    })
+}
+
+/// Fetches every page of a listing (e.g. "repos/coreos/tailor/pulls/1/commits"), following the
+/// "next" links given by GitHub. Rather than silently truncating the listing, this fails if there
+/// are more than `limit` items.
+pub fn paginate<T: DeserializeOwned>(
+    client: &Github,
+    endpoint: &str,
+    limit: usize,
+) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    let mut next = Some(format!("{}?per_page={}", endpoint, PAGE_SIZE));
+    while let Some(endpoint) = next {
+        trace!("Fetching page {}", endpoint);
+        let (headers, page): (_, Vec<T>) =
+            retry(|| client.get(&endpoint)).try_execute_with_headers()?;
+        items.extend(page);
+
+        if items.len() > limit {
+            return Err(format!("Listing contains more than {} items", limit).into());
+        }
+
+        next = next_page(client, &headers);
+    }
+    Ok(items)
+}
+
+/// Finds the endpoint of the next page of a response, if there is one.
+pub fn next_page(client: &Github, headers: &Headers) -> Option<String> {
+    headers
+        .get("Link")
+        .and_then(|link| link.to_str().ok())
+        .and_then(next_link)
+        .map(|url| {
+            url.trim_start_matches(client.api_url())
+                .trim_start_matches('/')
+                .to_string()
+        })
 }
 
-impl<'a> TryExecute for ::github_rs::repos::get::ContentsReference<'a> {}
-impl<'a> TryExecute for ::github_rs::repos::get::PullsNumber<'a> {}
-impl<'a> TryExecute for ::github_rs::repos::get::CollaboratorsUsernamePermission<'a> {}
-impl<'a> TryExecute for ::github_rs::repos::get::IssuesNumberComments<'a> {}
-impl<'a> TryExecute for ::github_rs::repos::get::PullsNumberCommits<'a> {}
+/// Finds the URL of the next page in the value of a Link header.
+fn next_link(header: &str) -> Option<&str> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';').map(str::trim);
+        let url = parts.next()?;
+        if parts.any(|param| param == r#"rel="next""#) {
+            Some(url.trim_start_matches('<').trim_end_matches('>'))
+        } else {
+            None
+        }
+    })
+}
+
+impl TryExecute for Request {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_link() {
+        assert_eq!(
+            next_link(
+                r#"<https://api.github.com/repositories/1/pulls/2/commits?page=2>; rel="next", <https://api.github.com/repositories/1/pulls/2/commits?page=5>; rel="last""#
+            ),
+            Some("https://api.github.com/repositories/1/pulls/2/commits?page=2")
+        );
+        assert_eq!(
+            next_link(
+                r#"<https://api.github.com/repositories/1/pulls/2/commits?page=1>; rel="prev", <https://api.github.com/repositories/1/pulls/2/commits?page=1>; rel="first""#
+            ),
+            None
+        );
+        assert_eq!(next_link(""), None);
+    }
+
+    #[test]
+    fn test_secondary_rate_limit() {
+        assert!(is_secondary_rate_limit(&json!({
+            "message": "You have exceeded a secondary rate limit. Please wait a few minutes before you try again.",
+            "documentation_url": "https://docs.github.com/rest/overview/resources-in-the-rest-api#secondary-rate-limits",
+        })));
+        assert!(is_secondary_rate_limit(&json!({
+            "message": "You have triggered an abuse detection mechanism. Please wait a few minutes before you try again.",
+        })));
+        assert!(!is_secondary_rate_limit(&json!({
+            "message": "Resource not accessible by integration",
+        })));
+        assert!(!is_secondary_rate_limit(&json!([])));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let now = Utc.timestamp(1_500_000_000, 0);
+        let none = RateLimit::default();
+        let secs = |secs| Some(Duration::from_secs(secs));
+
+        assert_eq!(backoff(None, &none, now, 1), secs(1));
+        assert_eq!(backoff(Some(StatusCode::BAD_GATEWAY), &none, now, 2), secs(2));
+        assert_eq!(backoff(Some(StatusCode::SERVICE_UNAVAILABLE), &none, now, 3), secs(4));
+        assert_eq!(backoff(Some(StatusCode::BAD_GATEWAY), &none, now, MAX_ATTEMPTS), None);
+        assert_eq!(backoff(Some(StatusCode::NOT_FOUND), &none, now, 1), None);
+        assert_eq!(backoff(Some(StatusCode::FORBIDDEN), &none, now, 1), None);
+        assert_eq!(backoff(Some(StatusCode::TOO_MANY_REQUESTS), &none, now, 1), secs(1));
+
+        let abuse = RateLimit {
+            retry_after: Some(30),
+            ..RateLimit::default()
+        };
+        assert_eq!(backoff(Some(StatusCode::FORBIDDEN), &abuse, now, 1), secs(30));
+
+        let secondary = RateLimit {
+            remaining: Some(4990),
+            secondary: true,
+            ..RateLimit::default()
+        };
+        assert_eq!(backoff(Some(StatusCode::FORBIDDEN), &secondary, now, 2), secs(2));
+
+        let exhausted = RateLimit {
+            remaining: Some(0),
+            reset: Some(1_500_000_010),
+            ..RateLimit::default()
+        };
+        assert_eq!(backoff(Some(StatusCode::FORBIDDEN), &exhausted, now, 1), secs(11));
+
+        let distant = RateLimit {
+            reset: Some(1_500_003_600),
+            ..exhausted
+        };
+        assert_eq!(backoff(Some(StatusCode::FORBIDDEN), &distant, now, 1), None);
+    }
+}
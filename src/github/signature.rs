@@ -0,0 +1,103 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use errors::*;
+use hmac::{Hmac, Mac};
+use iron;
+use sha2::Sha256;
+
+pub const HEADER: &str = "X-Hub-Signature-256";
+
+/// The set of secrets which are accepted when verifying webhook signatures. More than one secret
+/// may be active at a time so that they can be rotated without dropping events.
+#[derive(Clone)]
+pub struct Secrets(pub Vec<String>);
+
+impl iron::typemap::Key for Secrets {
+    type Value = Secrets;
+}
+
+impl Secrets {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Verifies the signature (e.g. "sha256=abcd...") against the raw body of the request. The
+    /// signature is accepted if any of the secrets produce a matching digest.
+    pub fn verify(&self, body: &[u8], signature: Option<&str>) -> Result<()> {
+        let signature = signature.ok_or_else(|| Error::from("Missing signature"))?;
+        let mut parts = signature.trim().splitn(2, '=');
+        let digest = match (parts.next(), parts.next()) {
+            (Some("sha256"), Some(digest)) => decode_hex(digest)?,
+            _ => return Err("Unsupported signature algorithm".into()),
+        };
+
+        for secret in &self.0 {
+            let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes())
+                .map_err(|_| Error::from("Invalid secret"))?;
+            mac.input(body);
+            if mac.verify(&digest).is_ok() {
+                return Ok(());
+            }
+        }
+
+        Err("Signature does not match".into())
+    }
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return Err("Malformed signature".into());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| "Malformed signature".into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Example from GitHub's webhook documentation
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn test_verify() {
+        let secrets = Secrets(vec![SECRET.to_string()]);
+        assert!(secrets.verify(BODY, Some(SIGNATURE)).is_ok());
+        assert!(secrets.verify(b"Hello, World?", Some(SIGNATURE)).is_err());
+        assert!(secrets.verify(BODY, None).is_err());
+        assert!(secrets.verify(BODY, Some("sha1=757107ea")).is_err());
+        assert!(secrets.verify(BODY, Some("sha256=75710")).is_err());
+        assert!(secrets.verify(BODY, Some("sha256=zz")).is_err());
+    }
+
+    #[test]
+    fn test_verify_rotation() {
+        let secrets = Secrets(vec!["old secret".to_string(), SECRET.to_string()]);
+        assert!(secrets.verify(BODY, Some(SIGNATURE)).is_ok());
+
+        let secrets = Secrets(vec!["old secret".to_string()]);
+        assert!(secrets.verify(BODY, Some(SIGNATURE)).is_err());
+    }
+}
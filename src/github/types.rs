@@ -23,13 +23,63 @@ pub struct Author {
     pub github_login: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CheckRun {
+    pub id: u64,
+    pub head_sha: String,
+    pub pull_requests: Vec<CheckPullRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckRuns {
+    pub check_runs: Vec<CheckRun>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckPullRequest {
+    pub number: usize,
+    pub head: CheckReference,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckReference {
+    pub sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckSuite {
+    pub head_sha: String,
+    pub pull_requests: Vec<CheckPullRequest>,
+}
+
 #[derive(Deserialize)]
 pub struct Collaborator {
     pub permission: Permission,
+    pub role_name: Option<String>,
 }
 
-#[derive(Deserialize, Value)]
+impl Collaborator {
+    /// The collaborator's permission, taking into account the finer-grained roles (e.g. maintain)
+    /// which aren't reflected in the legacy permission. Custom roles fall back to the legacy
+    /// permission, which reflects the role they are based on.
+    pub fn effective_permission(&self) -> Permission {
+        match self.role_name.as_ref().map(String::as_str) {
+            Some("admin") => Permission::Admin,
+            Some("maintain") => Permission::Maintain,
+            Some("write") => Permission::Write,
+            Some("triage") => Permission::Triage,
+            Some("read") => Permission::Read,
+            _ => self.permission,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Value)]
 pub struct Comment {
+    #[value(hidden)]
+    pub id: u64,
+    #[value(hidden)]
+    pub html_url: String,
     pub user: User,
     pub body: String,
     pub created_at: DateTime<Utc>,
@@ -43,6 +93,12 @@ pub struct Commit {
     pub committer: User,
 }
 
+#[derive(Deserialize)]
+pub struct CommitDetails {
+    pub stats: Stats,
+    pub files: Vec<File>,
+}
+
 #[derive(Deserialize)]
 pub struct CommitBody {
     pub author: Author,
@@ -54,7 +110,12 @@ pub struct CommitBody {
 pub struct CommitReference {
     pub sha: String,
     pub label: String,
+    #[serde(rename = "ref")]
+    #[value(hidden)]
+    pub reference: String,
     pub user: User,
+    #[value(hidden)]
+    pub repo: Option<Repository>,
 }
 
 #[derive(Deserialize)]
@@ -65,6 +126,16 @@ pub struct Content {
 #[derive(Debug, Deserialize)]
 pub struct Empty {}
 
+#[derive(Deserialize, Value)]
+pub struct File {
+    pub filename: String,
+    pub status: String,
+    pub additions: usize,
+    pub deletions: usize,
+    pub previous_filename: Option<String>,
+    pub patch: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ErrorResponse {
     pub message: String,
@@ -81,41 +152,228 @@ pub struct Error {
 
 #[derive(Debug, Deserialize)]
 pub struct Event {
-    pub repository: Repository,
+    pub repository: Option<Repository>,
     pub action: Option<String>,
+    pub check_run: Option<CheckRun>,
+    pub check_suite: Option<CheckSuite>,
+    pub comment: Option<Comment>,
     pub hook: Option<Empty>,
+    pub installation: Option<Installation>,
+    pub issue: Option<Issue>,
+    pub member: Option<User>,
+    pub organization: Option<Organization>,
     pub pull_request: Option<PullRequest>,
+    pub team: Option<Team>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Installation {
+    pub id: u64,
 }
 
-#[derive(Deserialize, PartialEq)]
+#[derive(Debug, Deserialize)]
+pub struct Issue {
+    pub number: usize,
+    pub pull_request: Option<Empty>,
+}
+
+#[derive(Debug, Deserialize, Value)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Milestone {
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Organization {
+    pub login: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
 pub enum Permission {
     #[serde(rename = "admin")]
     Admin,
+    #[serde(rename = "maintain")]
+    Maintain,
     #[serde(rename = "write")]
     Write,
+    #[serde(rename = "triage")]
+    Triage,
     #[serde(rename = "read")]
     Read,
     #[serde(rename = "none")]
     None,
 }
 
+impl Permission {
+    /// Whether this permission grants at least as much access as the other.
+    pub fn includes(self, other: Permission) -> bool {
+        self.rank() >= other.rank()
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Permission::None => 0,
+            Permission::Read => 1,
+            Permission::Triage => 2,
+            Permission::Write => 3,
+            Permission::Maintain => 4,
+            Permission::Admin => 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Team {
+    pub slug: String,
+}
+
+#[derive(Deserialize)]
+pub struct TeamMembership {
+    pub state: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PullRequest {
     pub user: User,
     pub number: usize,
+    pub html_url: String,
+    pub state: String,
     pub title: String,
     pub body: Option<String>,
     pub base: CommitReference,
     pub head: CommitReference,
+    // These are only included when the pull request is fetched, not in every webhook payload
+    #[serde(default)]
+    pub commits: usize,
+    #[serde(default)]
+    pub changed_files: usize,
+    pub labels: Vec<Label>,
+    #[serde(default)]
+    pub draft: bool,
+    pub milestone: Option<Milestone>,
+    #[serde(default)]
+    pub assignees: Vec<User>,
+    #[serde(default)]
+    pub requested_reviewers: Vec<User>,
+    pub mergeable_state: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Repository {
     pub owner: User,
     pub name: String,
+    pub default_branch: String,
+}
+
+#[derive(Deserialize, Value)]
+pub struct Review {
+    pub user: User,
+    pub state: String,
+    pub submitted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default, Deserialize, Value)]
+pub struct Stats {
+    pub additions: usize,
+    pub deletions: usize,
+    pub total: usize,
 }
 
 #[derive(Debug, Deserialize, Value)]
 pub struct User {
     pub login: String,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_review_event() {
+        // The pull request in review events omits the commit and file counts
+        let event: Event = serde_json::from_str(
+            r#"{
+              "action": "submitted",
+              "review": {
+                "id": 237895671,
+                "user": { "login": "octocat", "id": 1, "type": "User" },
+                "body": null,
+                "commit_id": "ec26c3e57ca3a959ca5aad62de7213c562f8c821",
+                "submitted_at": "2019-05-15T15:20:38Z",
+                "state": "commented",
+                "html_url": "https://github.com/Codertocat/Hello-World/pull/2#pullrequestreview-237895671",
+                "pull_request_url": "https://api.github.com/repos/Codertocat/Hello-World/pulls/2",
+                "author_association": "OWNER"
+              },
+              "pull_request": {
+                "url": "https://api.github.com/repos/Codertocat/Hello-World/pulls/2",
+                "id": 279147437,
+                "html_url": "https://github.com/Codertocat/Hello-World/pull/2",
+                "number": 2,
+                "state": "open",
+                "locked": false,
+                "title": "Update the README with new information.",
+                "user": { "login": "Codertocat", "id": 21031067, "type": "User" },
+                "body": "This is a pretty simple change that we need to pull into master.",
+                "created_at": "2019-05-15T15:20:33Z",
+                "updated_at": "2019-05-15T15:20:38Z",
+                "closed_at": null,
+                "merged_at": null,
+                "merge_commit_sha": "c4295bd74fb0f4fda03689c3df3f2803b658fd85",
+                "assignee": null,
+                "assignees": [],
+                "requested_reviewers": [],
+                "requested_teams": [],
+                "labels": [],
+                "milestone": null,
+                "head": {
+                  "label": "Codertocat:changes",
+                  "ref": "changes",
+                  "sha": "ec26c3e57ca3a959ca5aad62de7213c562f8c821",
+                  "user": { "login": "Codertocat", "id": 21031067, "type": "User" },
+                  "repo": {
+                    "id": 186853002,
+                    "name": "Hello-World",
+                    "full_name": "Codertocat/Hello-World",
+                    "owner": { "login": "Codertocat", "id": 21031067, "type": "User" },
+                    "default_branch": "master"
+                  }
+                },
+                "base": {
+                  "label": "Codertocat:master",
+                  "ref": "master",
+                  "sha": "f95f852bd8fca8fcc58a9a2d6c842781e32a215e",
+                  "user": { "login": "Codertocat", "id": 21031067, "type": "User" },
+                  "repo": {
+                    "id": 186853002,
+                    "name": "Hello-World",
+                    "full_name": "Codertocat/Hello-World",
+                    "owner": { "login": "Codertocat", "id": 21031067, "type": "User" },
+                    "default_branch": "master"
+                  }
+                },
+                "author_association": "OWNER"
+              },
+              "repository": {
+                "id": 186853002,
+                "name": "Hello-World",
+                "full_name": "Codertocat/Hello-World",
+                "owner": { "login": "Codertocat", "id": 21031067, "type": "User" },
+                "default_branch": "master"
+              },
+              "sender": { "login": "Codertocat", "id": 21031067, "type": "User" }
+            }"#,
+        ).unwrap();
+
+        let pr = event.pull_request.unwrap();
+        assert_eq!(pr.number, 2);
+        assert_eq!(pr.head.sha, "ec26c3e57ca3a959ca5aad62de7213c562f8c821");
+        assert_eq!(pr.commits, 0);
+        assert_eq!(pr.changed_files, 0);
+    }
+}
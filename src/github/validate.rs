@@ -13,25 +13,48 @@
 // limitations under the License.
 
 use base64;
-use config;
+use command::{self, Command};
+use config::{self, ConfigSource};
 use errors::*;
 use expr;
 use expr::ast::Value;
+use github::cache::{PermissionCache, ReactionCache};
+use github::client::Github;
 use github::types;
-use github::TryExecute;
-use github_rs::client::Github;
+use github::{self, TryExecute};
+use reqwest::StatusCode;
+use report::{CommitReport, Exemption, Outcome, Report, RuleReport};
 use serde_yaml;
+use std::collections::{HashMap, HashSet};
+use url::form_urlencoded;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
 use worker;
 
+const CONFIG_PATH: &str = ".github/tailor.yaml";
+
+/// The most files which GitHub will list for a single commit.
+const MAX_COMMIT_FILES: usize = 3000;
+
 #[derive(Value)]
 struct PullRequest {
     user: types::User,
+    number: usize,
+    #[value(hidden)]
+    html_url: String,
     title: String,
     body: Option<String>,
     commits: Vec<Commit>,
     comments: Vec<types::Comment>,
     base: types::CommitReference,
     head: types::CommitReference,
+    files: Vec<types::File>,
+    labels: Vec<types::Label>,
+    reviews: Vec<types::Review>,
+    requested_reviewers: Vec<types::User>,
+    assignees: Vec<types::User>,
+    draft: bool,
+    milestone: Option<String>,
+    mergeable_state: Option<String>,
 }
 
 #[derive(Value)]
@@ -41,30 +64,151 @@ struct Commit {
     committer: types::Author,
     title: String,
     description: String,
+    files: Vec<types::File>,
+    stats: types::Stats,
 }
 
-pub fn pull_request(job: &worker::PullRequestJob, client: &Github) -> Result<Vec<String>> {
-    let pr = fetch_pull_request(client, &job.owner, &job.repo, job.number)?;
-    let repo = fetch_repo_config(client, &job.owner, &job.repo, &pr)?;
-    let exemptions = find_exemptions(client, &job.owner, &job.repo, &pr)?;
+pub fn pull_request(
+    job: &worker::PullRequestJob,
+    client: &Github,
+    settings: &worker::Settings,
+    cache: &PermissionCache,
+    reactions: &ReactionCache,
+) -> Result<Report> {
+    let mut report = Report::default();
+    let mut pr = fetch_pull_request(
+        client,
+        &job.owner,
+        &job.repo,
+        job.number,
+        &job.head_sha,
+        settings,
+        &mut report,
+    )?;
+    let repo = fetch_repo_config(
+        client,
+        &job.owner,
+        &job.repo,
+        &pr,
+        settings.config_source,
+        &mut report,
+    )?;
+    let exemptions = run_commands(
+        client,
+        &job.owner,
+        &job.repo,
+        &pr,
+        &repo,
+        cache,
+        reactions,
+        &settings.login,
+    )?;
 
-    let mut failures = Vec::new();
-    let input = pr.into();
-    for rule in repo.rules.iter().filter(|rule| {
-        !exemptions.contains(&rule.name) && !exemptions.contains(&String::from("all"))
+    // Listing the files of each commit takes a request per commit, so it's only done if a rule
+    // looks at them
+    if repo.rules.iter().any(|rule| {
+        ["files", "stats"].iter().any(|field| {
+            expr::uses_element_field(&rule.expression, field).unwrap_or(true)
+        })
     }) {
-        let result = expr::eval(&rule.expression, &input).chain_err(|| {
+        fetch_commit_files(client, &job.owner, &job.repo, &mut pr.commits, &mut report)?;
+    }
+
+    let head_sha = pr.head.sha.clone();
+    report.url = Some(pr.html_url.clone());
+    report.comment = repo.report == Some(config::Reporting::Comment);
+    let target = config::Target {
+        base: pr.base.reference.clone(),
+        head: pr.head.reference.clone(),
+        files: pr.files.iter().map(|file| file.filename.clone()).collect(),
+        labels: pr.labels.iter().map(|label| label.name.clone()).collect(),
+        author: pr.user.login.clone(),
+    };
+
+    let input = pr.into();
+    // An exemption from "all" rules is only reported as dropped once, rather than for each rule
+    let mut dropped = HashSet::new();
+    for rule in repo.rules {
+        let applies = rule.when.applies(&target);
+        let name = if exemptions.contains_key(&rule.name) {
+            rule.name.clone()
+        } else {
+            "all".to_string()
+        };
+        let exemption = exemptions
+            .get(&name)
+            .filter(|_| applies)
+            .and_then(|exemption| {
+                if rule.sticky || exemption.commit.as_ref() == Some(&head_sha) {
+                    return Some(exemption.clone());
+                }
+
+                if dropped.insert(name.clone()) {
+                    report.notices.push(format!(
+                        "The exemption from {} granted by {} was dropped because commits were pushed after it was granted{}",
+                        if name == "all" { "all rules" } else { &name },
+                        exemption.user,
+                        match exemption.commit {
+                            Some(ref commit) => format!(" (at {})", &commit[..commit.len().min(7)]),
+                            None => String::new(),
+                        }
+                    ));
+                }
+                None
+            });
+        let context = || {
             format!(
                 r#"Failed to run "{}" from "{}/{}""#,
                 rule.name, job.owner, job.repo
             )
-        })?;
+        };
+        let (outcome, explanation) = if !applies {
+            (Outcome::NotApplicable, expr::Explanation::default())
+        } else if exemption.is_some() {
+            (Outcome::Exempted, expr::Explanation::default())
+        } else if expr::eval(&rule.expression, &input).chain_err(context)? {
+            (Outcome::Passed, expr::Explanation::default())
+        } else {
+            // The rule has already been evaluated, so failing to explain it shouldn't fail the
+            // whole evaluation
+            let explanation = expr::explain(&rule.expression, &input).unwrap_or_else(|err| {
+                warn!("{}: {}", context(), err);
+                expr::Explanation::default()
+            });
+            (Outcome::Failed, explanation)
+        };
+        let commits = failed_commits(explanation.elements);
 
-        if !result {
-            failures.push(format!("Failed {} ({})", rule.name, rule.description))
-        }
+        report.rules.push(RuleReport {
+            name: rule.name,
+            description: rule.description,
+            severity: rule.severity,
+            exemption,
+            outcome,
+            commits,
+            explanation: explanation.reasons,
+        })
     }
-    Ok(failures)
+    Ok(report)
+}
+
+/// Picks out the commits from the elements which caused the rule to fail, if it checks the
+/// commits. Each commit is only listed once, even if it failed more than one check.
+fn failed_commits(elements: Vec<Value>) -> Vec<CommitReport> {
+    let mut seen = HashSet::new();
+    elements
+        .into_iter()
+        .filter_map(|element| match element {
+            Value::Dictionary(mut commit) => match (commit.remove("sha"), commit.remove("title")) {
+                (Some(Value::String(sha)), Some(Value::String(title))) => {
+                    Some(CommitReport { sha, title })
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|commit| seen.insert(commit.sha.clone()))
+        .collect()
 }
 
 fn fetch_repo_config(
@@ -72,96 +216,550 @@ fn fetch_repo_config(
     owner: &str,
     repo: &str,
     pr: &PullRequest,
+    source: ConfigSource,
+    report: &mut Report,
 ) -> Result<config::Config> {
     trace!("Fetching repo config for {}/{}", owner, repo);
-    let config: types::Content = client
-        .get()
-        .repos()
-        .owner(owner)
-        .repo(repo)
-        .contents()
-        .path(".github/tailor.yaml")
-        .reference(&pr.head.sha)
-        .try_execute()
-        .chain_err(|| format!("Failed to fetch repo configuration for {}/{}", owner, repo))?;
-    match config.content {
-        Some(content) => Ok(serde_yaml::from_slice(&base64::decode_config(
-            &content,
-            base64::MIME,
-        )?)?),
+    let modified = pr.files.iter().any(|file| {
+        file.filename == CONFIG_PATH
+            || file.previous_filename.as_ref().map_or(false, |name| name == CONFIG_PATH)
+    });
+    if modified {
+        report.notices.push(format!(
+            "This pull request modifies the Tailor configuration ({}); the configuration from {} was used",
+            CONFIG_PATH,
+            match source {
+                ConfigSource::Base => "the base branch",
+                ConfigSource::Default => "the default branch",
+                ConfigSource::Head => "the pull request",
+            }
+        ));
+    }
+
+    let reference = match source {
+        ConfigSource::Base => &pr.base.reference,
+        ConfigSource::Default => match pr.base.repo {
+            Some(ref repository) => &repository.default_branch,
+            None => return Err("Pull request does not include the base repository".into()),
+        },
+        ConfigSource::Head => &pr.head.sha,
+    };
+    let config = fetch_config_file(client, owner, repo, CONFIG_PATH, Some(reference.as_str()))?;
+
+    match config {
+        Some(content) => resolve_extends(client, serde_yaml::from_slice(&content)?, &mut Vec::new()),
         None => {
             warn!("Repository {}/{} has no tailor configuration", owner, repo);
-            Ok(config::Config { rules: Vec::new() })
+            Ok(config::Config::default())
         }
     }
 }
 
-fn find_exemptions(
+/// Recursively fetches the configurations that this one extends and merges them together.
+fn resolve_extends(
+    client: &Github,
+    config: config::Config,
+    visited: &mut Vec<String>,
+) -> Result<config::Config> {
+    let source = match config.extends {
+        Some(ref source) => source.clone(),
+        None => return Ok(config),
+    };
+
+    if visited.contains(&source.to_string()) {
+        return Err(format!("Configuration {} is extended more than once (cycle?)", source).into());
+    }
+    visited.push(source.to_string());
+
+    trace!("Fetching inherited configuration {}", source);
+    let parent = match fetch_config_file(
+        client,
+        &source.owner,
+        &source.repo,
+        &source.path,
+        source.reference.as_ref().map(String::as_str),
+    )? {
+        Some(content) => serde_yaml::from_slice(&content)
+            .chain_err(|| format!("Failed to parse inherited configuration {}", source))?,
+        None => return Err(format!("Inherited configuration {} does not exist", source).into()),
+    };
+
+    Ok(config.merge(resolve_extends(client, parent, visited)?))
+}
+
+fn fetch_config_file(
+    client: &Github,
+    owner: &str,
+    repo: &str,
+    path: &str,
+    reference: Option<&str>,
+) -> Result<Option<Vec<u8>>> {
+    trace!(
+        "Fetching {}/{}:{} at {}",
+        owner,
+        repo,
+        path,
+        reference.unwrap_or("default branch")
+    );
+    // The path and reference come from the configuration, so they may need escaping
+    let path = path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    let endpoint = match reference {
+        Some(reference) => format!(
+            "repos/{}/{}/contents/{}?ref={}",
+            owner,
+            repo,
+            path,
+            form_urlencoded::byte_serialize(reference.as_bytes()).collect::<String>()
+        ),
+        None => format!("repos/{}/{}/contents/{}", owner, repo, path),
+    };
+    let config = match github::retry(|| client.get(&endpoint)).execute::<types::Content>() {
+        Ok((_, StatusCode::NOT_FOUND, _)) => return Ok(None),
+        Ok((_, StatusCode::OK, Some(config))) => Ok(config),
+        Ok((_, status, _)) => Err(format!("Received {} from GitHub", status).into()),
+        Err(err) => Err(err),
+    }.chain_err(|| format!("Failed to fetch configuration from {}/{}", owner, repo))?;
+
+    match config.content {
+        Some(content) => Ok(Some(base64::decode_config(&content, base64::MIME)?)),
+        None => Ok(None),
+    }
+}
+
+/// Carries out the commands in each of the comments on the pull request, in the order in which
+/// they were made, and returns the resulting exemptions, keyed by rule name. Each command is
+/// acknowledged with a reaction when it is first processed, so that the commenter knows that it
+/// was seen.
+fn run_commands(
     client: &Github,
     owner: &str,
     repo: &str,
     pr: &PullRequest,
-) -> Result<Vec<String>> {
-    let mut exemptions = Vec::new();
+    config: &config::Config,
+    cache: &PermissionCache,
+    reactions: &ReactionCache,
+    login: &str,
+) -> Result<HashMap<String, Exemption>> {
+    let mut exemptions = HashMap::new();
+    let authorizer = Authorizer::new(client, owner, repo, cache);
+    let react = |comment: &types::Comment, content| {
+        react(client, owner, repo, reactions, comment, content)
+    };
     for comment in &pr.comments {
-        // TODO ALEX
-        if (&(comment.body)).starts_with("tailor disable") {
-            let mut split = comment.body.as_str().split("tailor disable");
-            split.next();
-            if let Some(disabled_check) = split.next() {
-                trace!(
-                    "Fetching repo collaborator status for {}",
-                    comment.user.login
+        for command in command::parse(&comment.body) {
+            trace!("Found command {:?} from {}", command, comment.user.login);
+            let mut denied = Vec::new();
+            if let Command::Disable { ref rules, .. } | Command::Enable { ref rules } = command {
+                for rule in rules {
+                    if !may_exempt(&authorizer, config, rule, &comment.user.login)? {
+                        denied.push(rule.as_str());
+                    }
+                }
+            }
+
+            if !denied.is_empty() {
+                react(comment, "-1");
+                reply_once(
+                    client,
+                    owner,
+                    repo,
+                    pr,
+                    login,
+                    comment,
+                    "denied",
+                    &denied
+                        .iter()
+                        .map(|rule| {
+                            format!(
+                                "@{}: you are not allowed to exempt pull requests from **{}**; that requires {}.",
+                                comment.user.login,
+                                rule,
+                                config.authority(rule)
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
                 );
-                let collaborator: types::Collaborator = client
-                    .get()
-                    .repos()
-                    .owner(owner)
-                    .repo(repo)
-                    .collaborators()
-                    .username(&comment.user.login)
-                    .permission()
-                    .try_execute()
-                    .chain_err(|| "Failed to fetch collaborator data")?;
-                if collaborator.permission == types::Permission::Admin {
-                    exemptions.push(disabled_check.trim().to_string());
+                continue;
+            }
+
+            match command {
+                Command::Disable { rules, reason } => {
+                    let commit = record_exemption(client, owner, repo, pr, login, comment, &rules);
+                    for rule in rules {
+                        exemptions.insert(
+                            rule,
+                            Exemption {
+                                user: comment.user.login.clone(),
+                                reason: reason.clone(),
+                                url: comment.html_url.clone(),
+                                created_at: comment.created_at,
+                                commit: commit.clone(),
+                            },
+                        );
+                    }
+                }
+                Command::Enable { rules } => for rule in rules {
+                    if rule == "all" {
+                        exemptions.clear();
+                    } else {
+                        exemptions.remove(&rule);
+                    }
+                },
+                Command::Recheck => {}
+                Command::Explain { rule } => {
+                    reply_once(
+                        client,
+                        owner,
+                        repo,
+                        pr,
+                        login,
+                        comment,
+                        "explain",
+                        &explain(config, &comment.user.login, &rule),
+                    );
+                }
+                Command::Unknown(_) => {
+                    react(comment, "confused");
+                    continue;
                 }
             }
+            react(comment, "+1");
         }
     }
 
     Ok(exemptions)
 }
 
+/// Finds the head of the pull request when the exemption was granted: the last commit which was
+/// committed before the comment. Commit dates are chosen by whoever pushed the commits though, so
+/// that commit is also recorded in a reply the first time the exemption is processed, and later
+/// commits can't move it on from there.
+fn record_exemption(
+    client: &Github,
+    owner: &str,
+    repo: &str,
+    pr: &PullRequest,
+    login: &str,
+    comment: &types::Comment,
+    rules: &[String],
+) -> Option<String> {
+    let granted = pr.commits
+        .iter()
+        .take_while(|commit| commit.committer.date <= comment.created_at)
+        .last()?;
+
+    let marker = format!("<!-- tailor exempted {} ", comment.id);
+    let recorded = find_reply(pr, login, &marker).and_then(|reply| {
+        let start = reply.body.find(&marker)? + marker.len();
+        reply.body[start..].split_whitespace().next().map(String::from)
+    });
+    if let Some(recorded) = recorded {
+        // Whichever commit came first is used; one which is no longer in the pull request (e.g.
+        // after a force push) gets the exemption dropped either way
+        let position = |sha: &str| pr.commits.iter().position(|commit| commit.sha == sha);
+        return match (position(&recorded), position(&granted.sha)) {
+            (Some(r), Some(g)) if r > g => Some(granted.sha.clone()),
+            _ => Some(recorded),
+        };
+    }
+
+    if let Err(err) = reply(
+        client,
+        owner,
+        repo,
+        pr.number,
+        format!(
+            "{}{} -->\n@{}: exempted this pull request from {} as of {}.",
+            marker,
+            granted.sha,
+            comment.user.login,
+            rules
+                .iter()
+                .map(|rule| format!("**{}**", rule))
+                .collect::<Vec<_>>()
+                .join(", "),
+            &granted.sha[..granted.sha.len().min(7)]
+        ),
+    ) {
+        warn!("Failed to record exemption on pull request {}: {}", pr.number, err);
+    }
+    Some(granted.sha.clone())
+}
+
+/// Whether the user may grant (or revoke) an exemption from the named rule. Exemptions from "all"
+/// rules require the authority to exempt each of them.
+fn may_exempt(
+    authorizer: &Authorizer,
+    config: &config::Config,
+    rule: &str,
+    login: &str,
+) -> Result<bool> {
+    let mut authorities = vec![config.authority(rule)];
+    if rule == "all" {
+        authorities.extend(config.rules.iter().map(|r| config.authority(&r.name)));
+    }
+
+    for authority in &authorities {
+        if !authorizer.allows(authority, login)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Checks whether users meet the criteria of an authority, remembering the permissions and team
+/// memberships that it looks up in the shared cache.
+struct Authorizer<'a> {
+    client: &'a Github,
+    owner: &'a str,
+    repo: &'a str,
+    cache: &'a PermissionCache,
+}
+
+impl<'a> Authorizer<'a> {
+    fn new(
+        client: &'a Github,
+        owner: &'a str,
+        repo: &'a str,
+        cache: &'a PermissionCache,
+    ) -> Authorizer<'a> {
+        Authorizer {
+            client,
+            owner,
+            repo,
+            cache,
+        }
+    }
+
+    fn allows(&self, authority: &config::Authority, login: &str) -> Result<bool> {
+        if authority.users.iter().any(|user| user == login) {
+            return Ok(true);
+        }
+
+        if let Some(permission) = authority.permission {
+            if self.permission(login)?.includes(permission) {
+                return Ok(true);
+            }
+        }
+
+        for team in &authority.teams {
+            if self.is_member(team, login)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn permission(&self, login: &str) -> Result<types::Permission> {
+        if let Some(permission) = self.cache.permission(self.owner, self.repo, login) {
+            return Ok(permission);
+        }
+
+        trace!("Fetching repo collaborator status for {}", login);
+        let endpoint = format!(
+            "repos/{}/{}/collaborators/{}/permission",
+            self.owner, self.repo, login
+        );
+        let collaborator: types::Collaborator = github::retry(|| self.client.get(&endpoint))
+            .try_execute()
+            .chain_err(|| "Failed to fetch collaborator data")?;
+
+        let permission = collaborator.effective_permission();
+        self.cache
+            .insert_permission(self.owner, self.repo, login, permission);
+        Ok(permission)
+    }
+
+    fn is_member(&self, team: &str, login: &str) -> Result<bool> {
+        let (org, slug) = match team.find('/') {
+            Some(i) => (&team[..i], &team[i + 1..]),
+            None => (self.owner, team),
+        };
+
+        if let Some(member) = self.cache.membership(org, slug, login) {
+            return Ok(member);
+        }
+
+        trace!("Fetching membership of {} in {}/{}", login, org, slug);
+        let endpoint = format!("orgs/{}/teams/{}/memberships/{}", org, slug, login);
+        let member = match github::retry(|| self.client.get(&endpoint))
+            .execute::<types::TeamMembership>()
+        {
+            Ok((_, StatusCode::NOT_FOUND, _)) => false,
+            Ok((_, StatusCode::OK, Some(membership))) => membership.state == "active",
+            Ok((_, status, _)) => {
+                return Err(format!("Received {} while fetching team membership", status).into())
+            }
+            Err(err) => return Err(err).chain_err(|| "Failed to fetch team membership"),
+        };
+
+        self.cache.insert_membership(org, slug, login, member);
+        Ok(member)
+    }
+}
+
+/// Describes the named rule, or lists the available rules if there is no such rule.
+fn explain(config: &config::Config, login: &str, rule: &str) -> String {
+    match config.rules.iter().find(|r| r.name == rule) {
+        Some(rule) => format!(
+            "@{}: **{}** checks that {}.\n\n```\n{}\n```",
+            login, rule.name, rule.description, rule.expression
+        ),
+        None => format!(
+            "@{}: there is no rule named **{}**. The rules are: {}.",
+            login,
+            rule,
+            config
+                .rules
+                .iter()
+                .map(|r| format!("**{}**", r.name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Replies to a comment, unless a reply of the same kind has already been made. The replies are
+/// tagged with a hidden marker so that they can be found on later evaluations.
+fn reply_once(
+    client: &Github,
+    owner: &str,
+    repo: &str,
+    pr: &PullRequest,
+    login: &str,
+    comment: &types::Comment,
+    kind: &str,
+    body: &str,
+) {
+    let marker = format!("<!-- tailor {} {} -->", kind, comment.id);
+    if find_reply(pr, login, &marker).is_some() {
+        return;
+    }
+
+    if let Err(err) = reply(client, owner, repo, pr.number, format!("{}\n{}", marker, body)) {
+        warn!("Failed to comment on pull request {}: {}", pr.number, err);
+    }
+}
+
+/// Finds Tailor's reply with the given marker. Comments by anyone else are ignored, since they
+/// could copy the marker.
+fn find_reply<'a>(pr: &'a PullRequest, login: &str, marker: &str) -> Option<&'a types::Comment> {
+    pr.comments
+        .iter()
+        .find(|c| c.user.login == login && c.body.contains(marker))
+}
+
+/// Reacts to a comment, unless the reaction has already been added. Reactions are idempotent, so
+/// this is retried (and is harmless to repeat once the cache has forgotten the reaction).
+fn react(
+    client: &Github,
+    owner: &str,
+    repo: &str,
+    reactions: &ReactionCache,
+    comment: &types::Comment,
+    content: &str,
+) {
+    if reactions.contains(comment.id, content) {
+        return;
+    }
+
+    let endpoint = format!(
+        "repos/{}/{}/issues/comments/{}/reactions",
+        owner, repo, comment.id
+    );
+    match github::retry(|| client.post(&endpoint, json!({ "content": content })))
+        .try_execute::<types::Empty>()
+    {
+        Ok(_) => reactions.insert(comment.id, content),
+        Err(err) => warn!("Failed to react to comment {}: {}", comment.id, err),
+    }
+}
+
+fn reply(client: &Github, owner: &str, repo: &str, number: usize, body: String) -> Result<()> {
+    client
+        .post(
+            &format!("repos/{}/{}/issues/{}/comments", owner, repo, number),
+            json!({ "body": body }),
+        )
+        .try_execute::<types::Empty>()
+        .map(|_| ())
+}
+
+/// Lists the files changed by each of the commits, along with their diff statistics.
+fn fetch_commit_files(
+    client: &Github,
+    owner: &str,
+    repo: &str,
+    commits: &mut [Commit],
+    report: &mut Report,
+) -> Result<()> {
+    for commit in commits {
+        trace!("Fetching commit {}", commit.sha);
+        let mut next = Some(format!("repos/{}/{}/commits/{}", owner, repo, commit.sha));
+        while let Some(endpoint) = next {
+            let (headers, details): (_, types::CommitDetails) =
+                github::retry(|| client.get(&endpoint))
+                    .try_execute_with_headers()
+                    .chain_err(|| format!("Failed to fetch commit {}", commit.sha))?;
+            commit.files.extend(details.files);
+            commit.stats = details.stats;
+            next = github::next_page(client, &headers);
+        }
+
+        // GitHub stops listing the files of a commit after the first 3000
+        if commit.files.len() >= MAX_COMMIT_FILES {
+            warn!("Files of commit {} may have been truncated", commit.sha);
+            report.notices.push(format!(
+                "Only the first {} files changed by commit {} could be checked",
+                MAX_COMMIT_FILES,
+                &commit.sha[..commit.sha.len().min(7)]
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn fetch_pull_request(
     client: &Github,
     owner: &str,
     repo: &str,
     number: usize,
+    head_sha: &str,
+    settings: &worker::Settings,
+    report: &mut Report,
 ) -> Result<PullRequest> {
     trace!("Fetching pull request {}/{}: {}", owner, repo, number);
-    let pr: types::PullRequest = client
-        .get()
-        .repos()
-        .owner(owner)
-        .repo(repo)
-        .pulls()
-        .number(&number.to_string())
+    let endpoint = format!("repos/{}/{}/pulls/{}", owner, repo, number);
+    let pr: types::PullRequest = github::retry(|| client.get(&endpoint))
         .try_execute()
         .chain_err(|| "Failed to fetch pull request")?;
 
+    // There's no point in evaluating (and reporting on) a head which has already been replaced
+    if pr.head.sha != head_sha {
+        bail!(ErrorKind::Superseded(pr.head.sha));
+    }
+
     let commits = {
         trace!("Fetching pull request commits");
-        let raw_commits: Vec<types::Commit> = client
-            .get()
-            .repos()
-            .owner(owner)
-            .repo(repo)
-            .pulls()
-            .number(&number.to_string())
-            .commits()
-            .try_execute()
-            .chain_err(|| "Failed to fetch pull request commits")?;
+        let raw_commits: Vec<types::Commit> = github::paginate(
+            client,
+            &format!("repos/{}/{}/pulls/{}/commits", owner, repo, number),
+            settings.max_items,
+        ).chain_err(|| "Failed to fetch pull request commits")?;
+
+        // GitHub stops listing the commits of a pull request after the first 250
+        if raw_commits.len() != pr.commits {
+            return Err(format!(
+                "Only {} of the {} commits in the pull request could be fetched",
+                raw_commits.len(),
+                pr.commits
+            ).into());
+        }
 
         let mut commits = Vec::new();
         for c in raw_commits {
@@ -195,30 +793,69 @@ fn fetch_pull_request(
                 },
                 title,
                 description,
+                // These are filled in later, if they're needed
+                files: Vec::new(),
+                stats: types::Stats::default(),
             })
         }
         commits
     };
 
     trace!("Fetching pull request comments");
-    let comments: Vec<types::Comment> = client
-        .get()
-        .repos()
-        .owner(owner)
-        .repo(repo)
-        .issues()
-        .number(&number.to_string())
-        .comments()
-        .try_execute()
-        .chain_err(|| "Failed to fetch pull request comments")?;
+    let comments: Vec<types::Comment> = github::paginate(
+        client,
+        &format!("repos/{}/{}/issues/{}/comments", owner, repo, number),
+        settings.max_items,
+    ).chain_err(|| "Failed to fetch pull request comments")?;
+
+    trace!("Fetching pull request files");
+    let files: Vec<types::File> = github::paginate(
+        client,
+        &format!("repos/{}/{}/pulls/{}/files", owner, repo, number),
+        settings.max_items,
+    ).chain_err(|| "Failed to fetch pull request files")?;
+
+    // GitHub stops listing the files of a pull request after the first 3000
+    if files.len() < pr.changed_files {
+        warn!(
+            "Only {} of the {} files in {}/{}: {} could be fetched",
+            files.len(),
+            pr.changed_files,
+            owner,
+            repo,
+            number
+        );
+        report.notices.push(format!(
+            "Only {} of the {} changed files could be checked",
+            files.len(),
+            pr.changed_files
+        ));
+    }
+
+    trace!("Fetching pull request reviews");
+    let reviews: Vec<types::Review> = github::paginate(
+        client,
+        &format!("repos/{}/{}/pulls/{}/reviews", owner, repo, number),
+        settings.max_items,
+    ).chain_err(|| "Failed to fetch pull request reviews")?;
 
     Ok(PullRequest {
         user: pr.user,
+        number: pr.number,
+        html_url: pr.html_url,
         title: pr.title,
         body: pr.body,
         base: pr.base,
         head: pr.head,
         commits,
         comments,
+        files,
+        labels: pr.labels,
+        reviews,
+        requested_reviewers: pr.requested_reviewers,
+        assignees: pr.assignees,
+        draft: pr.draft,
+        milestone: pr.milestone.map(|milestone| milestone.title),
+        mergeable_state: pr.mergeable_state,
     })
 }
@@ -0,0 +1,384 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use errors::*;
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use sync;
+use worker::{PullRequestJob, RefreshJob};
+
+/// The fewest entries after which the journal is compacted while work is outstanding.
+const MIN_COMPACTION_ENTRIES: usize = 1000;
+
+/// Records the jobs which are waiting to be (or are being) evaluated, along with the commits which
+/// have been marked as pending but not yet given a final status, so that neither is lost when
+/// Tailor is restarted. The journal is an append-only file of JSON entries, one per line. Once
+/// most of the entries are obsolete (or nothing is outstanding), it is compacted by writing the
+/// outstanding work to a new file which then replaces it.
+pub struct Journal {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The path of the journal file; if there isn't one, nothing is persisted
+    path: Option<PathBuf>,
+    file: Option<File>,
+    /// The number of entries in the file
+    entries: usize,
+    /// Whether the work from before the restart has yet to be queued again, in which case the
+    /// journal can't be compacted without losing it
+    recovering: bool,
+    next_id: u64,
+    jobs: HashMap<u64, Job>,
+    /// The pull requests whose heads are marked as pending, keyed by (owner, repo, sha)
+    pending: HashMap<(String, String, String), RefreshJob>,
+}
+
+/// An evaluation job, as it is recorded in the journal.
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Job {
+    PullRequest(PullRequestJob),
+    Refresh(RefreshJob),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    Queued {
+        id: u64,
+        job: Job,
+    },
+    Finished {
+        id: u64,
+    },
+    Pending {
+        sha: String,
+        pull_request: RefreshJob,
+    },
+    Reported {
+        owner: String,
+        repo: String,
+        sha: String,
+    },
+}
+
+/// The work which was outstanding when Tailor last stopped.
+#[derive(Default)]
+pub struct Recovery {
+    /// The jobs which were queued or in progress
+    pub jobs: Vec<Job>,
+    /// The pull requests which were left with a pending status
+    pub pending: Vec<RefreshJob>,
+}
+
+impl Journal {
+    /// A journal which doesn't persist anything.
+    pub fn disabled() -> Journal {
+        Journal {
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Opens the journal at the given path, returning the work which it recorded as outstanding.
+    /// The journal keeps its entries until `recovered` is called, on the assumption that the
+    /// outstanding work will then have been queued (and therefore recorded) again.
+    pub fn open(path: &Path) -> Result<(Journal, Recovery)> {
+        let mut state = State::default();
+        if path.exists() {
+            let file = File::open(path)
+                .chain_err(|| format!("Failed to open journal {}", path.display()))?;
+            for line in BufReader::new(file).lines() {
+                let line = line.chain_err(|| format!("Failed to read journal {}", path.display()))?;
+                state.entries += 1;
+                match serde_json::from_str(&line) {
+                    Ok(entry) => state.apply(entry),
+                    // The last entry may have been cut short by a crash
+                    Err(err) => warn!("Ignoring malformed journal entry ({}): {}", err, line),
+                }
+            }
+        }
+
+        let mut jobs: Vec<_> = state.jobs.drain().collect();
+        jobs.sort_by_key(|&(id, _)| id);
+        let recovery = Recovery {
+            jobs: jobs.into_iter().map(|(_, job)| job).collect(),
+            pending: state.pending.drain().map(|(_, pr)| pr).collect(),
+        };
+
+        state.file = Some(open_for_append(path)?);
+        state.path = Some(path.to_path_buf());
+        state.recovering = true;
+
+        Ok((
+            Journal {
+                state: Mutex::new(state),
+            },
+            recovery,
+        ))
+    }
+
+    /// Records that the recovered work has been queued again, so the entries from before the
+    /// restart are no longer needed.
+    pub fn recovered(&self) {
+        let mut state = sync::lock(&self.state);
+        state.recovering = false;
+        state.compact();
+    }
+
+    /// Records a newly queued job, returning the ID with which it should later be finished.
+    pub fn queued(&self, job: Job) -> u64 {
+        let mut state = sync::lock(&self.state);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.record(Entry::Queued { id, job });
+        id
+    }
+
+    pub fn finished(&self, id: u64) {
+        sync::lock(&self.state).record(Entry::Finished { id });
+    }
+
+    /// Records that the head of the pull request has been marked as pending.
+    pub fn pending(&self, job: &PullRequestJob) {
+        sync::lock(&self.state).record(Entry::Pending {
+            sha: job.head_sha.clone(),
+            pull_request: RefreshJob {
+                owner: job.owner.clone(),
+                repo: job.repo.clone(),
+                number: job.number,
+                installation: job.installation,
+            },
+        });
+    }
+
+    /// Records that the commit has been given a final status.
+    pub fn reported(&self, owner: &str, repo: &str, sha: &str) {
+        let mut state = sync::lock(&self.state);
+        if state
+            .pending
+            .contains_key(&(owner.to_string(), repo.to_string(), sha.to_string()))
+        {
+            state.record(Entry::Reported {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                sha: sha.to_string(),
+            });
+        }
+    }
+}
+
+impl State {
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Queued { id, job } => {
+                self.next_id = self.next_id.max(id + 1);
+                self.jobs.insert(id, job);
+            }
+            Entry::Finished { id } => {
+                self.jobs.remove(&id);
+            }
+            Entry::Pending { sha, pull_request } => {
+                self.pending.insert(
+                    (pull_request.owner.clone(), pull_request.repo.clone(), sha),
+                    pull_request,
+                );
+            }
+            Entry::Reported { owner, repo, sha } => {
+                self.pending.remove(&(owner, repo, sha));
+            }
+        }
+    }
+
+    /// Applies the entry and appends it to the journal file. Failures are logged rather than
+    /// returned, since losing the journal shouldn't stop pull requests from being evaluated.
+    fn record(&mut self, entry: Entry) {
+        if let Err(err) = self.write(&entry) {
+            error!("Failed to write to journal: {}", err);
+        }
+        self.apply(entry);
+
+        let outstanding = self.jobs.len() + self.pending.len();
+        if outstanding == 0 || self.entries >= MIN_COMPACTION_ENTRIES.max(2 * outstanding) {
+            self.compact();
+        }
+    }
+
+    fn write(&mut self, entry: &Entry) -> Result<()> {
+        if let Some(ref mut file) = self.file {
+            let mut line = serde_json::to_vec(entry)?;
+            line.push(b'\n');
+            file.write_all(&line)
+                .and_then(|_| file.sync_data())
+                .chain_err(|| "Failed to append entry")?;
+            self.entries += 1;
+        }
+        Ok(())
+    }
+
+    /// Replaces the journal file with one which only records the outstanding work. On failure,
+    /// the existing file is kept (and appended to).
+    fn compact(&mut self) {
+        if self.recovering || self.entries == 0 {
+            return;
+        }
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return,
+        };
+
+        match self.rewrite(&path) {
+            Ok(file) => {
+                self.file = Some(file);
+                self.entries = self.jobs.len() + self.pending.len();
+            }
+            Err(err) => error!("Failed to compact journal: {}", err),
+        }
+    }
+
+    fn rewrite(&self, path: &Path) -> Result<File> {
+        let mut jobs: Vec<_> = self.jobs.iter().collect();
+        jobs.sort_by_key(|&(id, _)| *id);
+        let entries = jobs.into_iter()
+            .map(|(&id, job)| Entry::Queued {
+                id,
+                job: job.clone(),
+            })
+            .chain(self.pending.iter().map(|(key, pr)| Entry::Pending {
+                sha: key.2.clone(),
+                pull_request: pr.clone(),
+            }));
+
+        let mut contents = Vec::new();
+        for entry in entries {
+            contents.extend(serde_json::to_vec(&entry)?);
+            contents.push(b'\n');
+        }
+
+        let mut temp = path.as_os_str().to_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        File::create(&temp)
+            .and_then(|mut file| file.write_all(&contents).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temp, path))
+            .chain_err(|| format!("Failed to write {}", temp.display()))?;
+
+        open_for_append(path)
+    }
+}
+
+fn open_for_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .chain_err(|| format!("Failed to open journal {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_recovery() {
+        let path = env::temp_dir().join(format!("tailor-journal-{}", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let first = PullRequestJob {
+            owner: "coreos".into(),
+            repo: "tailor".into(),
+            number: 1,
+            head_sha: "a".into(),
+            installation: Some(1),
+        };
+        let second = PullRequestJob {
+            number: 2,
+            head_sha: "b".into(),
+            ..first.clone()
+        };
+
+        {
+            let (journal, recovery) = Journal::open(&path).unwrap();
+            assert!(recovery.jobs.is_empty() && recovery.pending.is_empty());
+            journal.recovered();
+
+            journal.pending(&first);
+            let id = journal.queued(Job::PullRequest(first.clone()));
+            journal.pending(&second);
+            journal.queued(Job::PullRequest(second.clone()));
+            journal.finished(id);
+            journal.reported("coreos", "tailor", "a");
+        }
+
+        // Until the work has been recovered, reopening the journal finds it again
+        for _ in 0..2 {
+            let (_, recovery) = Journal::open(&path).unwrap();
+            assert_eq!(recovery.jobs.len(), 1);
+            match recovery.jobs[0] {
+                Job::PullRequest(ref job) => assert_eq!(job.number, 2),
+                Job::Refresh(_) => panic!("expected a pull request"),
+            }
+            assert_eq!(recovery.pending.len(), 1);
+            assert_eq!(recovery.pending[0].number, 2);
+            assert_eq!(recovery.pending[0].installation, Some(1));
+        }
+
+        // Nothing is outstanding once the work is recovered, so the journal is empty
+        let (journal, _) = Journal::open(&path).unwrap();
+        journal.recovered();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        let id = journal.queued(Job::PullRequest(first));
+        journal.finished(id);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_compaction() {
+        let path = env::temp_dir().join(format!("tailor-compaction-{}", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let job = PullRequestJob {
+            owner: "coreos".into(),
+            repo: "tailor".into(),
+            number: 1,
+            head_sha: "a".into(),
+            installation: None,
+        };
+
+        let (journal, _) = Journal::open(&path).unwrap();
+        journal.recovered();
+        journal.pending(&job);
+        for _ in 0..MIN_COMPACTION_ENTRIES {
+            let id = journal.queued(Job::PullRequest(job.clone()));
+            journal.finished(id);
+        }
+        assert!(sync::lock(&journal.state).entries < MIN_COMPACTION_ENTRIES);
+
+        let lines = BufReader::new(File::open(&path).unwrap()).lines().count();
+        assert_eq!(lines, sync::lock(&journal.state).entries);
+
+        let (_, recovery) = Journal::open(&path).unwrap();
+        assert!(recovery.jobs.is_empty());
+        assert_eq!(recovery.pending.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -17,16 +17,19 @@ extern crate chrono;
 extern crate env_logger;
 #[macro_use]
 extern crate error_chain;
-extern crate github_rs;
 extern crate handlebars_iron;
+extern crate hmac;
 extern crate iron;
+extern crate jsonwebtoken;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate nom;
 extern crate params;
 extern crate persistent;
+extern crate rand;
 extern crate regex;
+extern crate reqwest;
 extern crate router;
 extern crate serde;
 #[macro_use]
@@ -34,26 +37,35 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate serde_yaml;
+extern crate sha2;
 extern crate snap;
 #[macro_use]
 extern crate structopt;
+extern crate url;
 #[macro_use]
 extern crate value_derive;
 
+mod command;
 mod config;
 mod errors;
 mod expr;
 mod github;
+mod journal;
+mod report;
+mod results;
 mod routes;
+mod sync;
 mod worker;
 
 use errors::*;
 use handlebars_iron::{DirectorySource, HandlebarsEngine};
 use iron::prelude::*;
 use log::LevelFilter;
+use reqwest::Url;
 use router::Router;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -67,16 +79,79 @@ struct Options {
     pub port: u16,
 
     #[structopt(long = "server-address", default_value = "localhost:8080")]
-    /// The socket address used to reach the server
+    /// The socket address used to reach the server (ignored if --public-url is given)
     pub server: String,
 
+    #[structopt(long = "public-url", parse(try_from_str = "parse_public_url"))]
+    /// The URL at which the server is reached (e.g. https://example.com/tailor), under which all
+    /// of the routes are served
+    pub public_url: Option<Url>,
+
     #[structopt(long = "templates", default_value = "assets/templates", parse(from_os_str))]
     /// The path to the templates, relative to the working directory
     pub templates: PathBuf,
 
     #[structopt(long = "token")]
     /// The GitHub access token to use for requests
-    pub token: String,
+    pub token: Option<String>,
+
+    #[structopt(long = "app-id")]
+    /// The ID of the GitHub App as which to authenticate (instead of a token)
+    pub app_id: Option<u64>,
+
+    #[structopt(long = "app-key", parse(from_os_str))]
+    /// The path to the GitHub App's PEM-encoded private key
+    pub app_key: Option<PathBuf>,
+
+    #[structopt(long = "github-api-url", default_value = "https://api.github.com")]
+    /// The URL of the GitHub API (e.g. https://github.example.com/api/v3 for GitHub Enterprise)
+    pub github_api_url: String,
+
+    #[structopt(long = "installation-api-url", parse(try_from_str = "parse_installation_url"))]
+    /// The URL of the GitHub API for one installation of the App, written as <id>=<url>; may be
+    /// repeated
+    pub installation_urls: Vec<(u64, String)>,
+
+    #[structopt(long = "checks")]
+    /// Report results by creating check runs instead of commit statuses (requires --app-id)
+    pub checks: bool,
+
+    #[structopt(long = "config-source", default_value = "base")]
+    /// The revision from which tailor.yaml is loaded: base, default, or head
+    pub config_source: config::ConfigSource,
+
+    #[structopt(long = "max-items", default_value = "3000")]
+    /// The maximum number of commits, comments, or files fetched for a pull request (GitHub lists
+    /// at most 3000 files)
+    pub max_items: usize,
+
+    #[structopt(long = "journal", parse(from_os_str))]
+    /// The path to a file in which queued jobs are recorded, so that they survive a restart
+    pub journal: Option<PathBuf>,
+
+    #[structopt(long = "results", default_value = "results", parse(from_os_str))]
+    /// The directory in which evaluation results are stored for the status pages
+    pub results: PathBuf,
+
+    #[structopt(long = "result-retention", default_value = "30")]
+    /// The number of days for which evaluation results are kept
+    pub result_retention: u64,
+
+    #[structopt(long = "max-results", default_value = "10000")]
+    /// The maximum number of evaluation results which are kept
+    pub max_results: usize,
+
+    #[structopt(long = "threads", default_value = "4")]
+    /// The number of pull requests which may be evaluated at once
+    pub threads: usize,
+
+    #[structopt(long = "permission-ttl", default_value = "300")]
+    /// The number of seconds for which collaborator permissions and team memberships are cached
+    pub permission_ttl: u64,
+
+    #[structopt(long = "webhook-secret", env = "TAILOR_WEBHOOK_SECRET")]
+    /// The secret used to verify GitHub webhook signatures; may be repeated to allow rotation
+    pub webhook_secrets: Vec<String>,
 
     #[structopt(short = "v", parse(from_occurrences))]
     /// Verbosity level
@@ -85,6 +160,30 @@ struct Options {
 
 quick_main!(run);
 
+fn parse_installation_url(s: &str) -> Result<(u64, String)> {
+    match s.find('=') {
+        Some(i) => Ok((
+            s[..i]
+                .parse()
+                .chain_err(|| format!("Invalid installation ID in {}", s))?,
+            s[i + 1..].to_string(),
+        )),
+        None => Err(format!("Expected <id>=<url>, found {}", s).into()),
+    }
+}
+
+fn parse_public_url(s: &str) -> Result<Url> {
+    let url = Url::parse(s).chain_err(|| format!("Invalid URL {}", s))?;
+    match url.scheme() {
+        "http" | "https" => {}
+        scheme => bail!("Unsupported scheme {} in {}", scheme, s),
+    }
+    if !url.has_host() || url.query().is_some() || url.fragment().is_some() {
+        bail!("Expected a URL with a host and no query or fragment, found {}", s);
+    }
+    Ok(url)
+}
+
 fn run() -> Result<()> {
     let opts = Options::from_args();
 
@@ -100,13 +199,85 @@ fn run() -> Result<()> {
         )
         .init();
 
+    if opts.webhook_secrets.is_empty() {
+        warn!("No webhook secret specified; webhook signatures will not be verified");
+    }
+
+    let credentials = match (opts.token, opts.app_id, opts.app_key) {
+        (Some(token), None, None) => github::auth::Credentials::Token(token),
+        (None, Some(id), Some(key)) => github::auth::Credentials::app(id, &key)
+            .chain_err(|| "Failed to load GitHub App credentials")?,
+        _ => bail!("Either --token or both --app-id and --app-key must be specified"),
+    };
+
+    if opts.checks && opts.app_id.is_none() {
+        bail!("Check runs can only be created when authenticated as a GitHub App");
+    }
+
+    if opts.threads == 0 {
+        bail!("At least one thread is needed to evaluate pull requests");
+    }
+
+    if !opts.installation_urls.is_empty() && opts.app_id.is_none() {
+        bail!("Installation API URLs can only be used when authenticated as a GitHub App");
+    }
+
+    let auth = github::auth::Authenticator::new(
+        credentials,
+        opts.github_api_url,
+        opts.installation_urls.into_iter().collect(),
+    ).chain_err(|| "Failed to create GitHub client")?;
+    let login = auth.login()
+        .chain_err(|| "Failed to look up Tailor's GitHub login")?;
+    debug!("Commenting as {}", login);
+
+    let cache = github::cache::PermissionCache::new(Duration::from_secs(opts.permission_ttl));
+
+    let results = results::ResultStore::open(
+        &opts.results,
+        Duration::from_secs(opts.result_retention * 24 * 60 * 60),
+        opts.max_results,
+    ).chain_err(|| "Failed to open result store")?;
+
+    let public_url = match opts.public_url {
+        Some(url) => url,
+        None => parse_public_url(&format!("http://{}", opts.server))
+            .chain_err(|| "Invalid server address")?,
+    };
+    // The routes are served under the URL's path, which (like the URL) has no trailing slash
+    let base_url = public_url.as_str().trim_end_matches('/').to_string();
+    let prefix = public_url.path().trim_end_matches('/').to_string();
+
+    let (journal, recovery) = match opts.journal {
+        Some(ref path) => journal::Journal::open(path).chain_err(|| "Failed to open journal")?,
+        None => (journal::Journal::disabled(), journal::Recovery::default()),
+    };
+
     debug!("Spawning worker thread");
-    let worker = worker::spawn(opts.token, opts.server)
+    let worker = worker::spawn(
+        auth,
+        worker::Settings {
+            public_url: base_url,
+            login: login.clone(),
+            checks: opts.checks,
+            config_source: opts.config_source,
+            max_items: opts.max_items,
+            threads: opts.threads,
+        },
+        cache.clone(),
+        results.clone(),
+        journal,
+    )
         .chain_err(|| "Failed to create status worker")?;
+    worker
+        .recover(recovery)
+        .chain_err(|| "Failed to recover queued jobs")?;
 
     let mut router = Router::new();
-    router.post("/hook", routes::handle_event, "github_webhook");
-    router.get("/status", routes::handle_status, "status");
+    router.post(format!("{}/hook", prefix), routes::handle_event, "github_webhook");
+    router.get(format!("{}/status", prefix), routes::handle_status, "status");
+    router.get(format!("{}/status/:id", prefix), routes::handle_result, "result");
+    router.get(format!("{}/queue", prefix), routes::handle_queue, "queue");
 
     let mut engine = HandlebarsEngine::new();
     engine.add(Box::new(DirectorySource::new(
@@ -120,6 +291,12 @@ fn run() -> Result<()> {
 
     let mut chain = Chain::new(router);
     chain.link(persistent::Write::<worker::Worker>::both(worker));
+    chain.link(persistent::Read::<github::signature::Secrets>::both(
+        github::signature::Secrets(opts.webhook_secrets),
+    ));
+    chain.link(persistent::Read::<routes::Login>::both(routes::Login(login)));
+    chain.link(persistent::Read::<github::cache::PermissionCache>::both(cache));
+    chain.link(persistent::Read::<results::ResultStore>::both(results));
     chain.link_after(engine);
 
     debug!("Starting web server");
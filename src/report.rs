@@ -0,0 +1,514 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::{DateTime, Utc};
+use config::Severity;
+use std::fmt::Write;
+
+/// The hidden marker which identifies the summary comment, so that it can be found and updated.
+pub const COMMENT_MARKER: &str = "<!-- tailor summary -->";
+
+/// The longest comment which GitHub accepts.
+const MAX_COMMENT_LENGTH: usize = 65536;
+
+/// The longest summary (or text) which GitHub accepts in a check run's output.
+const MAX_CHECK_OUTPUT_LENGTH: usize = 65535;
+
+/// Ends a check run's output which had to be cut short.
+const CHECK_OUTPUT_TRUNCATED: &str =
+    "...\n\nThe report is too long to show here; see the details for the rest.\n";
+
+/// The result of evaluating every rule against a pull request.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Report {
+    /// The pull request's page on GitHub
+    pub url: Option<String>,
+    /// Whether the results are also summarized in a comment on the pull request
+    #[serde(default)]
+    pub comment: bool,
+    pub rules: Vec<RuleReport>,
+    pub notices: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RuleReport {
+    pub name: String,
+    pub description: String,
+    pub severity: Severity,
+    pub outcome: Outcome,
+    pub exemption: Option<Exemption>,
+    /// The commits which caused the rule to fail, for rules of the form `.commits all(...)`
+    #[serde(default)]
+    pub commits: Vec<CommitReport>,
+    /// Why the rule failed (e.g. "commit abc1234: title length 63 is not < 51")
+    #[serde(default)]
+    pub explanation: Vec<String>,
+}
+
+/// A commit which caused a rule to fail.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CommitReport {
+    pub sha: String,
+    pub title: String,
+}
+
+impl CommitReport {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(7)]
+    }
+}
+
+/// The admin command which exempted the pull request from a rule.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Exemption {
+    pub user: String,
+    pub reason: Option<String>,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+    /// The head of the pull request when the exemption was granted
+    pub commit: Option<String>,
+}
+
+impl Exemption {
+    fn describe(&self) -> String {
+        match self.reason {
+            Some(ref reason) => format!("by {}: {}", self.user, reason),
+            None => format!("by {}", self.user),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Exempted,
+    NotApplicable,
+}
+
+impl RuleReport {
+    /// A short description of the outcome, suitable for a badge.
+    pub fn result(&self) -> &'static str {
+        match (self.outcome, self.severity) {
+            (Outcome::Passed, _) => "Passed",
+            (Outcome::Failed, Severity::Error) => "Failed",
+            (Outcome::Failed, Severity::Warning) => "Warning",
+            (Outcome::Failed, Severity::Notice) => "Notice",
+            (Outcome::Exempted, _) => "Exempted",
+            (Outcome::NotApplicable, _) => "Not applicable",
+        }
+    }
+
+    fn label(&self) -> String {
+        match (self.outcome, self.severity) {
+            (Outcome::Passed, _) => "passed".to_string(),
+            (Outcome::Failed, Severity::Error) => "failed".to_string(),
+            (Outcome::Failed, Severity::Warning) => "failed (warning)".to_string(),
+            (Outcome::Failed, Severity::Notice) => "failed (notice)".to_string(),
+            (Outcome::Exempted, _) => match self.exemption {
+                Some(ref exemption) => {
+                    format!("[exempted]({}) {}", exemption.url, exemption.describe())
+                }
+                None => "exempted".to_string(),
+            },
+            (Outcome::NotApplicable, _) => "not applicable".to_string(),
+        }
+    }
+}
+
+impl Report {
+    /// The failed rules of the given severity.
+    pub fn failed(&self, severity: Severity) -> Vec<&RuleReport> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.outcome == Outcome::Failed && rule.severity == severity)
+            .collect()
+    }
+
+    /// The failed rules which cause the pull request to fail.
+    pub fn failures(&self) -> Vec<&RuleReport> {
+        self.failed(Severity::Error)
+    }
+
+    /// A one-line summary, suitable for a status description.
+    pub fn summary(&self) -> String {
+        match (self.failures().len(), self.failed(Severity::Warning).len()) {
+            (0, 0) => "All checks passed".to_string(),
+            (0, 1) => "All checks passed, 1 warning".to_string(),
+            (0, warnings) => format!("All checks passed, {} warnings", warnings),
+            _ => "One or more checks failed".to_string(),
+        }
+    }
+
+    /// Lists each of the failures and notices, one per line.
+    pub fn lines(&self) -> Vec<String> {
+        let failed = |severity, label: &'static str| {
+            self.failed(severity)
+                .into_iter()
+                .map(move |rule| format!("{} {} ({})", label, rule.name, rule.description))
+        };
+
+        failed(Severity::Error, "Failed")
+            .chain(failed(Severity::Warning, "Warning:"))
+            .chain(failed(Severity::Notice, "Notice:"))
+            .chain(self.rules.iter().filter_map(|rule| {
+                rule.exemption
+                    .as_ref()
+                    .map(|exemption| format!("Exempted {} {}", rule.name, exemption.describe()))
+            }))
+            .chain(self.notices.iter().map(|notice| format!("Notice: {}", notice)))
+            .collect()
+    }
+
+    /// Renders a markdown table of every rule and its outcome, followed by any notices. The
+    /// details of each rule are rendered separately, by `to_details`.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = if self.rules.is_empty() {
+            "No rules are configured for this repository.\n".to_string()
+        } else {
+            let mut table = String::from("| Rule | Description | Result |\n|---|---|---|\n");
+            for rule in &self.rules {
+                let mut label = rule.label();
+                if !rule.commits.is_empty() {
+                    let shas: Vec<_> = rule.commits
+                        .iter()
+                        .map(|commit| commit.short_sha())
+                        .collect();
+                    write!(label, " in {}", shas.join(", ")).expect("write to string");
+                }
+                writeln!(
+                    table,
+                    "| {} | {} | {} |",
+                    escape(&rule.name),
+                    escape(&rule.description),
+                    escape(&label)
+                ).expect("write to string");
+            }
+            table
+        };
+
+        for notice in &self.notices {
+            write!(markdown, "\n**Notice:** {}\n", notice).expect("write to string");
+        }
+        truncate(&mut markdown, MAX_CHECK_OUTPUT_LENGTH, CHECK_OUTPUT_TRUNCATED);
+        markdown
+    }
+
+    /// Renders a section for each rule, giving its outcome along with the commits and reasons
+    /// which caused it to fail.
+    pub fn to_details(&self) -> String {
+        let mut details = String::new();
+        for rule in &self.rules {
+            write!(
+                details,
+                "### {}\n\n{}\n\n**Result:** {}\n",
+                rule.name,
+                rule.description,
+                rule.label()
+            ).expect("write to string");
+
+            if !rule.commits.is_empty() {
+                details.push_str("\nFailing commits:\n");
+                for commit in &rule.commits {
+                    writeln!(details, "- {} {}", commit.short_sha(), commit.title)
+                        .expect("write to string");
+                }
+            }
+            if !rule.explanation.is_empty() {
+                details.push_str("\nReasons:\n");
+                for reason in &rule.explanation {
+                    writeln!(details, "- {}", reason).expect("write to string");
+                }
+            }
+            details.push('\n');
+        }
+        truncate(&mut details, MAX_CHECK_OUTPUT_LENGTH, CHECK_OUTPUT_TRUNCATED);
+        details
+    }
+
+    /// Renders the summary comment, which lists the failed rules (and why they failed), or simply
+    /// notes that all of the checks pass. The details link to the full report, which is all that's
+    /// left of the reasons if there are too many to fit in a comment.
+    pub fn to_comment(&self, details: Option<&str>) -> String {
+        let mut comment = format!("{}\n", COMMENT_MARKER);
+        let failures = self.failures();
+        if failures.is_empty() {
+            comment.push_str("All checks pass.\n");
+            return comment;
+        }
+
+        comment.push_str("This pull request doesn't comply with the following rules:\n\n");
+        for rule in failures {
+            writeln!(
+                comment,
+                "- **{}**: {}",
+                escape_comment(&rule.name),
+                escape_comment(&rule.description)
+            ).expect("write to string");
+            for reason in &rule.explanation {
+                writeln!(comment, "  - {}", escape_comment(reason)).expect("write to string");
+            }
+        }
+
+        let footer = match details {
+            Some(details) => format!("\nSee the [full report]({}) for details.\n", details),
+            None => String::new(),
+        };
+        let truncated = match details {
+            Some(details) => format!(
+                "  - ...\n\nThe report is too long to show here; see the [full report]({}).\n",
+                details
+            ),
+            None => "  - ...\n\nThe report is too long to show here.\n".to_string(),
+        };
+        if !truncate(&mut comment, MAX_COMMENT_LENGTH - footer.len(), &truncated) {
+            comment.push_str(&footer);
+        }
+        comment
+    }
+}
+
+/// Cuts the text short at a line break if it's longer than the limit, leaving room to append the
+/// footer. Returns whether the text was cut short.
+fn truncate(text: &mut String, max: usize, footer: &str) -> bool {
+    if text.len() <= max {
+        return false;
+    }
+
+    let mut end = max - footer.len();
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let end = text[..end].rfind('\n').map_or(0, |i| i + 1);
+    text.truncate(end);
+    text.push_str(footer);
+    true
+}
+
+fn escape(cell: &str) -> String {
+    cell.replace('|', r"\|").replace('\n', " ")
+}
+
+/// Escapes text from the repository (e.g. commit titles) for use in a comment, so that any
+/// mentions don't notify anyone. The zero-width space keeps them from being recognized.
+fn escape_comment(text: &str) -> String {
+    text.replace('@', "@&#8203;").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let report = Report {
+            url: None,
+            comment: false,
+            rules: vec![
+                RuleReport {
+                    name: "title".into(),
+                    description: "titles | are short".into(),
+                    severity: Severity::Error,
+                    outcome: Outcome::Failed,
+                    exemption: None,
+                    commits: vec![CommitReport {
+                        sha: "0123456789abcdef".into(),
+                        title: "Add a title which is far too long".into(),
+                    }],
+                    explanation: vec!["commit 0123456: title length 33 is not < 20".into()],
+                },
+                RuleReport {
+                    name: "description".into(),
+                    description: "descriptions\nare wrapped".into(),
+                    severity: Severity::Error,
+                    outcome: Outcome::Exempted,
+                    exemption: Some(Exemption {
+                        user: "crawford".into(),
+                        reason: Some("imported | history".into()),
+                        url: "https://github.com/coreos/tailor/pull/1#issuecomment-1".into(),
+                        created_at: Utc::now(),
+                        commit: None,
+                    }),
+                    commits: Vec::new(),
+                    explanation: Vec::new(),
+                },
+                RuleReport {
+                    name: "body".into(),
+                    description: "has a body".into(),
+                    severity: Severity::Warning,
+                    outcome: Outcome::Failed,
+                    exemption: None,
+                    commits: Vec::new(),
+                    explanation: Vec::new(),
+                },
+            ],
+            notices: vec!["the configuration changed".into()],
+        };
+
+        assert_eq!(
+            report.to_markdown(),
+            "| Rule | Description | Result |\n\
+             |---|---|---|\n\
+             | title | titles \\| are short | failed in 0123456 |\n\
+             | description | descriptions are wrapped | [exempted](https://github.com/coreos/tailor/pull/1#issuecomment-1) by crawford: imported \\| history |\n\
+             | body | has a body | failed (warning) |\n\
+             \n**Notice:** the configuration changed\n"
+        );
+        assert_eq!(
+            report.to_details(),
+            "### title\n\ntitles | are short\n\n**Result:** failed\n\
+             \nFailing commits:\n\
+             - 0123456 Add a title which is far too long\n\
+             \nReasons:\n\
+             - commit 0123456: title length 33 is not < 20\n\n\
+             ### description\n\ndescriptions\nare wrapped\n\n\
+             **Result:** [exempted](https://github.com/coreos/tailor/pull/1#issuecomment-1) by crawford: imported | history\n\n\
+             ### body\n\nhas a body\n\n**Result:** failed (warning)\n\n"
+        );
+        assert_eq!(
+            report.lines(),
+            vec![
+                "Failed title (titles | are short)",
+                "Warning: body (has a body)",
+                "Exempted description by crawford: imported | history",
+                "Notice: the configuration changed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comment() {
+        let mut report = Report::default();
+        report.rules.push(RuleReport {
+            name: "body".into(),
+            description: "has a body".into(),
+            severity: Severity::Warning,
+            outcome: Outcome::Failed,
+            exemption: None,
+            commits: Vec::new(),
+            explanation: Vec::new(),
+        });
+        assert_eq!(report.to_comment(None), "<!-- tailor summary -->\nAll checks pass.\n");
+
+        report.rules.push(RuleReport {
+            name: "title".into(),
+            description: "titles are short".into(),
+            severity: Severity::Error,
+            outcome: Outcome::Failed,
+            exemption: None,
+            commits: Vec::new(),
+            explanation: vec!["commit 0123456: title length 63 is not < 51".into()],
+        });
+        assert_eq!(
+            report.to_comment(Some("https://tailor.example.com/status/1")),
+            "<!-- tailor summary -->\n\
+             This pull request doesn't comply with the following rules:\n\n\
+             - **title**: titles are short\n  \
+             - commit 0123456: title length 63 is not < 51\n\
+             \nSee the [full report](https://tailor.example.com/status/1) for details.\n"
+        );
+
+        report.rules[1].description = "titles don't ping\n@crawford".into();
+        report.rules[1].explanation = vec![
+            "commit 0123456: title \"Thank @crawford\" matches \"@\"".into();
+            MAX_COMMENT_LENGTH / 40
+        ];
+        let comment = report.to_comment(Some("https://tailor.example.com/status/1"));
+        assert!(comment.len() <= MAX_COMMENT_LENGTH);
+        assert!(comment.starts_with(
+            "<!-- tailor summary -->\n\
+             This pull request doesn't comply with the following rules:\n\n\
+             - **title**: titles don't ping @&#8203;crawford\n  \
+             - commit 0123456: title \"Thank @&#8203;crawford\" matches \"@&#8203;\"\n"
+        ));
+        assert!(comment.ends_with(
+            "\n  - commit 0123456: title \"Thank @&#8203;crawford\" matches \"@&#8203;\"\n  \
+             - ...\n\
+             \nThe report is too long to show here; see the \
+             [full report](https://tailor.example.com/status/1).\n"
+        ));
+    }
+
+    #[test]
+    fn test_check_output() {
+        let mut report = Report::default();
+        report.rules.push(RuleReport {
+            name: "title".into(),
+            description: "titles are short".into(),
+            severity: Severity::Error,
+            outcome: Outcome::Failed,
+            exemption: None,
+            commits: Vec::new(),
+            explanation: vec![
+                "commit 0123456: title length 63 is not < 51".into();
+                MAX_CHECK_OUTPUT_LENGTH / 40
+            ],
+        });
+
+        let details = report.to_details();
+        assert!(details.len() <= MAX_CHECK_OUTPUT_LENGTH);
+        assert!(details.ends_with(&format!(
+            "- commit 0123456: title length 63 is not < 51\n{}",
+            CHECK_OUTPUT_TRUNCATED
+        )));
+        assert_eq!(
+            report.to_markdown(),
+            "| Rule | Description | Result |\n|---|---|---|\n| title | titles are short | failed |\n"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut report = Report::default();
+        assert_eq!(report.summary(), "All checks passed");
+
+        let failed = RuleReport {
+            name: "a".into(),
+            description: String::new(),
+            severity: Severity::Notice,
+            outcome: Outcome::Failed,
+            exemption: None,
+            commits: Vec::new(),
+            explanation: Vec::new(),
+        };
+        report.rules.push(failed.clone());
+        assert_eq!(report.summary(), "All checks passed");
+
+        report.rules.push(RuleReport {
+            severity: Severity::Warning,
+            ..failed.clone()
+        });
+        assert_eq!(report.summary(), "All checks passed, 1 warning");
+
+        report.rules.push(RuleReport {
+            severity: Severity::Warning,
+            ..failed.clone()
+        });
+        assert_eq!(report.summary(), "All checks passed, 2 warnings");
+
+        report.rules.push(RuleReport {
+            severity: Severity::Error,
+            outcome: Outcome::Passed,
+            ..failed.clone()
+        });
+        assert_eq!(report.summary(), "All checks passed, 2 warnings");
+
+        report.rules.push(RuleReport {
+            severity: Severity::Error,
+            ..failed
+        });
+        assert_eq!(report.summary(), "One or more checks failed");
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2017 CoreOS, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::{DateTime, Utc};
+use errors::*;
+use iron;
+use rand;
+use report::Report;
+use serde_json;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use sync;
+
+/// How often old results are pruned while saving new ones.
+const PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Stores the results of evaluations on disk, one JSON file per result, so that status pages can
+/// be served from a short, unguessable ID rather than from the contents of the URL. Results are
+/// forgotten once they are older than the retention period, and only the newest are kept once
+/// there are more than the limit. Clones share the same directory.
+#[derive(Clone)]
+pub struct ResultStore {
+    dir: PathBuf,
+    retention: Duration,
+    max_results: usize,
+    last_pruned: Arc<Mutex<Instant>>,
+}
+
+/// The outcome of evaluating one head of a pull request.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct StoredResult {
+    pub owner: String,
+    pub repo: String,
+    pub number: usize,
+    pub head_sha: String,
+    pub created_at: DateTime<Utc>,
+    /// The one-line summary given in the status
+    pub description: String,
+    /// Each of the failures and notices
+    pub lines: Vec<String>,
+    /// The outcome of each rule, unless the rules couldn't be evaluated
+    #[serde(default)]
+    pub report: Option<Report>,
+}
+
+impl ResultStore {
+    /// Opens the store in the given directory (creating it if needed) and prunes any results
+    /// which are past their retention.
+    pub fn open(dir: &Path, retention: Duration, max_results: usize) -> Result<ResultStore> {
+        fs::create_dir_all(dir)
+            .chain_err(|| format!("Failed to create result directory {}", dir.display()))?;
+
+        let store = ResultStore {
+            dir: dir.to_path_buf(),
+            retention,
+            max_results,
+            last_pruned: Arc::new(Mutex::new(Instant::now())),
+        };
+        store.prune()?;
+        Ok(store)
+    }
+
+    /// Saves the result, returning the ID under which it can be loaded.
+    pub fn save(&self, result: &StoredResult) -> Result<String> {
+        let id = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+        let path = self.path(&id);
+
+        // Write to a temporary file first, so that a half-written result is never served
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, serde_json::to_vec(result)?)
+            .and_then(|_| fs::rename(&temp, &path))
+            .chain_err(|| format!("Failed to write result {}", path.display()))?;
+
+        let prune = {
+            let mut last_pruned = sync::lock(&self.last_pruned);
+            if last_pruned.elapsed() >= Duration::from_secs(PRUNE_INTERVAL_SECS) {
+                *last_pruned = Instant::now();
+                true
+            } else {
+                false
+            }
+        };
+        if prune {
+            if let Err(err) = self.prune() {
+                error!("Failed to prune results: {}", err);
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Loads the result with the given ID, if it exists and hasn't expired.
+    pub fn load(&self, id: &str) -> Result<Option<StoredResult>> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+
+        let path = self.path(id);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).chain_err(|| format!("Failed to open result {}", path.display()))
+            }
+        };
+
+        let modified = file.metadata()
+            .and_then(|metadata| metadata.modified())
+            .chain_err(|| format!("Failed to read result {}", path.display()))?;
+        if self.expired(modified) {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_reader(file)?))
+    }
+
+    /// Removes the results which are past their retention, followed by the oldest results beyond
+    /// the limit, returning the number which were removed.
+    pub fn prune(&self) -> Result<usize> {
+        let mut results = Vec::new();
+        for entry in fs::read_dir(&self.dir)
+            .chain_err(|| format!("Failed to read result directory {}", self.dir.display()))?
+        {
+            let path = entry
+                .chain_err(|| format!("Failed to read result directory {}", self.dir.display()))?
+                .path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                let modified = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .chain_err(|| format!("Failed to read result {}", path.display()))?;
+                results.push((modified, path));
+            }
+        }
+
+        // Newest first, so that everything after the limit can be removed
+        results.sort_by(|a, b| b.cmp(a));
+        let mut removed = 0;
+        for (i, (modified, path)) in results.into_iter().enumerate() {
+            if i >= self.max_results || self.expired(modified) {
+                fs::remove_file(&path)
+                    .chain_err(|| format!("Failed to remove result {}", path.display()))?;
+                removed += 1;
+            }
+        }
+
+        if removed > 0 {
+            info!("Pruned {} results", removed);
+        }
+        Ok(removed)
+    }
+
+    fn expired(&self, modified: SystemTime) -> bool {
+        // A time in the future (e.g. after the clock changed) is treated as brand new
+        modified
+            .elapsed()
+            .map(|age| age >= self.retention)
+            .unwrap_or(false)
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+impl iron::typemap::Key for ResultStore {
+    type Value = ResultStore;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_store() {
+        let dir = env::temp_dir().join(format!("tailor-results-store-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = ResultStore::open(&dir, Duration::from_secs(60), 10).unwrap();
+        let saved = StoredResult {
+            owner: "coreos".into(),
+            repo: "tailor".into(),
+            number: 1,
+            head_sha: "a".into(),
+            created_at: Utc::now(),
+            description: "One or more checks failed".into(),
+            lines: vec!["Failed title (titles are short)".into()],
+            report: None,
+        };
+        let id = store.save(&saved).unwrap();
+        assert_eq!(id.len(), 32);
+
+        assert_eq!(store.load(&id).unwrap(), Some(saved));
+        assert_eq!(store.load("0123456789abcdef").unwrap(), None);
+        assert_eq!(store.load("../journal").unwrap(), None);
+        assert_eq!(store.load("").unwrap(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retention() {
+        let dir = env::temp_dir().join(format!("tailor-results-retention-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let result = StoredResult {
+            owner: "coreos".into(),
+            repo: "tailor".into(),
+            number: 1,
+            head_sha: "a".into(),
+            created_at: Utc::now(),
+            description: "All checks passed".into(),
+            lines: Vec::new(),
+            report: None,
+        };
+
+        let store = ResultStore::open(&dir, Duration::from_secs(0), 10).unwrap();
+        let id = store.save(&result).unwrap();
+        assert_eq!(store.load(&id).unwrap(), None);
+        assert_eq!(store.prune().unwrap(), 1);
+
+        let store = ResultStore::open(&dir, Duration::from_secs(60), 1).unwrap();
+        store.save(&result).unwrap();
+        store.save(&result).unwrap();
+        assert_eq!(store.prune().unwrap(), 1);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -13,18 +13,65 @@
 // limitations under the License.
 
 use base64;
+use chrono::{DateTime, Utc};
 use errors::*;
+use github::cache::PermissionCache;
+use github::signature;
 use github::types::Event;
 use handlebars_iron::Template;
+use iron;
+use iron::headers::ContentType;
 use iron::prelude::*;
 use iron::status;
 use params::{Map, Params, Value};
 use persistent;
+use report;
+use results::{ResultStore, StoredResult};
+use router::Router;
 use serde_json;
 use snap;
 use std::io::Read;
+use std::str;
 use worker;
 
+/// The login with which Tailor comments, so that its own comments can be recognized.
+pub struct Login(pub String);
+
+impl iron::typemap::Key for Login {
+    type Value = Login;
+}
+
+/// The pull request actions which could change the outcome of the rules.
+const PULL_REQUEST_ACTIONS: &[&str] = &[
+    "opened",
+    "reopened",
+    "synchronize",
+    "edited",
+    "labeled",
+    "unlabeled",
+    "assigned",
+    "unassigned",
+    "milestoned",
+    "demilestoned",
+    "review_requested",
+    "review_request_removed",
+    "ready_for_review",
+    "converted_to_draft",
+];
+
+/// The review and comment actions which could change the outcome of the rules (e.g. by adding or
+/// removing an exemption).
+const REVIEW_ACTIONS: &[&str] = &["submitted", "edited", "dismissed"];
+const COMMENT_ACTIONS: &[&str] = &["created", "edited", "deleted"];
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+        .get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map(String::from)
+}
+
 pub fn handle_event(req: &mut Request) -> IronResult<Response> {
     let event: Event = {
         let mut body = String::new();
@@ -32,6 +79,16 @@ pub fn handle_event(req: &mut Request) -> IronResult<Response> {
             error!("Failed to read GitHub request: {}", err);
             IronError::new(err, (status::InternalServerError, "Failed to read request"))
         })?;
+
+        let secrets = req.get::<persistent::Read<signature::Secrets>>().unwrap();
+        if !secrets.is_empty() {
+            let signature = header(req, signature::HEADER);
+            if let Err(err) = secrets.verify(body.as_bytes(), signature.as_ref().map(String::as_str)) {
+                warn!("Rejecting GitHub request with invalid signature: {}", err);
+                return Ok(Response::with((status::Unauthorized, "Invalid signature")));
+            }
+        }
+
         serde_json::from_str(&body)
     }.map_err(|err| {
         error!("Failed to parse GitHub request: {}", err);
@@ -48,19 +105,84 @@ pub fn handle_event(req: &mut Request) -> IronResult<Response> {
         return Ok(Response::with(status::Ok));
     };
 
-    let pull_request = match event.pull_request {
-        Some(pull_request) => pull_request,
-        None => {
-            info!("Received GitHub event for something other than a pull request; ignoring.");
-            return Ok(Response::with((status::Ok, "Not a pull request")));
+    let login = req.get::<persistent::Read<Login>>().unwrap();
+    let kind = header(req, "X-GitHub-Event");
+    let action = event.action.clone().unwrap_or_default();
+    let accepts = |actions: &[&str]| actions.contains(&action.as_str());
+
+    // Changes to collaborators and team members invalidate the permissions which were cached when
+    // authorizing commands
+    match (
+        kind.as_ref().map(String::as_str),
+        &event.repository,
+        &event.organization,
+        &event.team,
+        &event.member,
+    ) {
+        (Some("member"), Some(repo), _, _, Some(member)) => {
+            let cache = req.get::<persistent::Read<PermissionCache>>().unwrap();
+            cache.invalidate_collaborator(&repo.owner.login, &repo.name, &member.login);
+            return Ok(Response::with((status::Ok, "Invalidated cached permission")));
+        }
+        (Some("membership"), _, Some(org), Some(team), Some(member)) => {
+            let cache = req.get::<persistent::Read<PermissionCache>>().unwrap();
+            cache.invalidate_member(&org.login, &team.slug, &member.login);
+            return Ok(Response::with((status::Ok, "Invalidated cached membership")));
+        }
+        _ => {}
+    }
+
+    // Each of the pull requests to be evaluated, along with its head (if known)
+    let pull_requests: Vec<(usize, Option<String>)> = match (
+        kind.as_ref().map(String::as_str),
+        event.pull_request,
+        event.issue,
+        event.check_run,
+        event.check_suite,
+    ) {
+        (Some("pull_request"), Some(pr), _, _, _) if accepts(PULL_REQUEST_ACTIONS) => {
+            vec![(pr.number, Some(pr.head.sha))]
+        }
+        (Some("pull_request_review"), Some(pr), _, _, _) if accepts(REVIEW_ACTIONS) => {
+            vec![(pr.number, Some(pr.head.sha))]
+        }
+        // Tailor's own comments (the summary and its replies to commands) can't change the
+        // outcome, so there's no need to evaluate the pull request again each time one is posted
+        (Some("issue_comment"), _, Some(ref issue), _, _)
+            if event.comment.as_ref().map_or(false, |comment| comment.user.login == login.0) =>
+        {
+            debug!("Ignoring Tailor's own comment on {}", issue.number);
+            return Ok(Response::with((status::Ok, "Ignoring own comment")));
+        }
+        (Some("issue_comment"), _, Some(issue), _, _)
+            if issue.pull_request.is_some() && accepts(COMMENT_ACTIONS) =>
+        {
+            vec![(issue.number, None)]
+        }
+        (Some("check_run"), _, _, Some(run), _) if accepts(&["rerequested"]) => run.pull_requests
+            .into_iter()
+            .map(|pr| (pr.number, Some(pr.head.sha)))
+            .collect(),
+        (Some("check_suite"), _, _, _, Some(suite)) if accepts(&["rerequested"]) => suite
+            .pull_requests
+            .into_iter()
+            .map(|pr| (pr.number, Some(pr.head.sha)))
+            .collect(),
+        (kind, _, _, _, _) => {
+            info!("Received GitHub event {:?} ({}); ignoring.", kind, action);
+            return Ok(Response::with((status::Ok, "Ignoring event")));
         }
     };
 
-    if event.action == Some("closed".into()) {
-        debug!("Received GitHub request for closed pull request; ignoring.");
-        return Ok(Response::with((status::Ok, "Ignoring closed pull request")));
-    }
+    let repository = match event.repository {
+        Some(repository) => repository,
+        None => {
+            info!("Received GitHub event {:?} without a repository; ignoring.", kind);
+            return Ok(Response::with((status::Ok, "Ignoring event")));
+        }
+    };
 
+    let installation = event.installation.map(|i| i.id);
     let w = req.get::<persistent::Write<worker::Worker>>().unwrap();
     let worker = match w.lock() {
         Ok(worker) => worker,
@@ -73,34 +195,30 @@ pub fn handle_event(req: &mut Request) -> IronResult<Response> {
         }
     };
 
-    if let Err(err) = worker.queue_status(
-        worker::State::Pending,
-        "The pull request has been received".to_string(),
-        None,
-        worker::Commit {
-            owner: event.repository.owner.login.clone(),
-            repo: event.repository.name.clone(),
-            sha: pull_request.head.sha.clone(),
-        },
-    ) {
-        error!("Failed to queue status: {}", err);
-        return Ok(Response::with((
-            status::InternalServerError,
-            format!("Failed to send struct to processing thread: {}", err),
-        )));
-    }
+    for (number, head_sha) in pull_requests {
+        let result = match head_sha {
+            Some(head_sha) => worker.queue_evaluation(worker::PullRequestJob {
+                owner: repository.owner.login.clone(),
+                repo: repository.name.clone(),
+                number,
+                head_sha,
+                installation,
+            }),
+            None => worker.queue_refresh(worker::RefreshJob {
+                owner: repository.owner.login.clone(),
+                repo: repository.name.clone(),
+                number,
+                installation,
+            }),
+        };
 
-    if let Err(err) = worker.queue_pull_request(worker::PullRequestJob {
-        owner: event.repository.owner.login,
-        repo: event.repository.name,
-        number: pull_request.number,
-        head_sha: pull_request.head.sha,
-    }) {
-        error!("Failed to queue pull request: {}", err);
-        return Ok(Response::with((
-            status::InternalServerError,
-            format!("Failed to send struct to processing thread: {}", err),
-        )));
+        if let Err(err) = result {
+            error!("Failed to queue pull request: {}", err);
+            return Ok(Response::with((
+                status::InternalServerError,
+                format!("Failed to send struct to processing thread: {}", err),
+            )));
+        }
     }
 
     Ok(Response::with((
@@ -109,6 +227,113 @@ pub fn handle_event(req: &mut Request) -> IronResult<Response> {
     )))
 }
 
+/// Reports the number of jobs waiting to be processed, for monitoring.
+pub fn handle_queue(req: &mut Request) -> IronResult<Response> {
+    let w = req.get::<persistent::Write<worker::Worker>>().unwrap();
+    let depth = match w.lock() {
+        Ok(worker) => worker.depth(),
+        Err(err) => {
+            error!("Failed to aquire worker.");
+            return Ok(Response::with((
+                status::InternalServerError,
+                format!("Failed to aquire worker: {}", err),
+            )));
+        }
+    };
+
+    let mut response = Response::with((status::Ok, json!(depth).to_string()));
+    response.headers.set(ContentType::json());
+    Ok(response)
+}
+
+/// Renders a result which was stored by the worker.
+pub fn handle_result(req: &mut Request) -> IronResult<Response> {
+    let id = req.extensions
+        .get::<Router>()
+        .and_then(|router| router.find("id"))
+        .unwrap_or("")
+        .to_string();
+    let store = req.get::<persistent::Read<ResultStore>>().unwrap();
+
+    match store.load(&id) {
+        Ok(Some(result)) => Ok(Response::with((
+            status::Ok,
+            Template::new("status", &result_page(&result)),
+        ))),
+        Ok(None) => Ok(Response::with((status::NotFound, "No such result"))),
+        Err(err) => {
+            error!("Failed to load result {}: {}", id, err);
+            Ok(Response::with((
+                status::InternalServerError,
+                "Failed to load result",
+            )))
+        }
+    }
+}
+
+/// Arranges a stored result for the status template, adding the links and labels which the
+/// template can't work out for itself.
+fn result_page(result: &StoredResult) -> serde_json::Value {
+    fn short_sha(sha: &str) -> &str {
+        &sha[..sha.len().min(7)]
+    }
+    fn timestamp(time: &DateTime<Utc>) -> String {
+        time.format("%Y-%m-%d %H:%M UTC").to_string()
+    }
+
+    let url = result.report.as_ref().and_then(|report| report.url.as_ref());
+    let commit_url = |sha: &str| url.map(|url| format!("{}/commits/{}", url, sha));
+
+    let mut page = json!({
+        "title": format!("{}/{}#{}", result.owner, result.repo, result.number),
+        "url": url,
+        "commit": {
+            "sha": short_sha(&result.head_sha),
+            "url": commit_url(&result.head_sha),
+        },
+        "description": result.description,
+        "created_at": timestamp(&result.created_at),
+    });
+
+    match result.report {
+        Some(ref report) => {
+            page["rules"] = report
+                .rules
+                .iter()
+                .map(|rule| {
+                    json!({
+                        "name": rule.name,
+                        "description": rule.description,
+                        "outcome": rule.outcome,
+                        "severity": rule.severity,
+                        "result": rule.result(),
+                        "exemption": rule.exemption.as_ref().map(|exemption| json!({
+                            "user": exemption.user,
+                            "reason": exemption.reason,
+                            "url": exemption.url,
+                            "created_at": timestamp(&exemption.created_at),
+                        })),
+                        "explanation": rule.explanation,
+                        "commits": rule.commits
+                            .iter()
+                            .map(|commit| json!({
+                                "sha": commit.short_sha(),
+                                "title": commit.title,
+                                "url": commit_url(&commit.sha),
+                            }))
+                            .collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            page["notices"] = json!(report.notices);
+        }
+        None => page["statuses"] = json!(result.lines),
+    }
+    page
+}
+
+/// Renders a result which was encoded into the URL. Statuses are no longer created this way, but
+/// the links on older commits still need to work.
 pub fn handle_status(req: &mut Request) -> IronResult<Response> {
     fn decode_message(params: &Map) -> Result<String> {
         match params.find(&["snap"]) {
@@ -12,30 +12,192 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use base64;
+use chrono::Utc;
+use config::ConfigSource;
 use errors::*;
-use github::types::Empty;
+use github::auth::Authenticator;
+use github::cache::{PermissionCache, ReactionCache};
+use github::client::Github;
+use github::types::{self, Empty};
 use github::{self, TryExecute};
-use github_rs::client;
 use iron;
-use snap;
+use journal::{self, Journal, Recovery};
+use report::{self, Report};
+use results::{ResultStore, StoredResult};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::sync::mpsc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::thread;
+use sync;
 
-impl<'a> TryExecute for ::github_rs::repos::post::Sha<'a> {}
+/// The number of reactions which are remembered, so that commands aren't acknowledged again.
+const MAX_REACTIONS: usize = 10_000;
 
+/// The longest that a status, check run, or summary comment waits before being retried, since
+/// everything queued behind it on the status thread waits too.
+const MAX_STATUS_BACKOFF_SECS: u64 = 10;
+
+/// Queues jobs for the background threads: evaluations (and refreshes) go to a pool of threads,
+/// while statuses and check runs go to a single thread, so that they are posted in order and
+/// aren't held up behind slow evaluations.
 #[derive(Clone)]
 pub struct Worker {
-    tx: mpsc::Sender<Job>,
+    evaluations: Arc<Queue>,
+    statuses: mpsc::Sender<Job>,
+    /// The number of statuses and check runs which haven't been posted yet
+    pending_statuses: Arc<AtomicUsize>,
+    journal: Arc<Journal>,
+    checks: bool,
+    /// The head of each pull request which is waiting to be evaluated, keyed by
+    /// (owner, repo, number)
+    queued: Arc<Mutex<HashMap<(String, String, usize), String>>>,
+    /// The ID of the in-progress check run for each commit, keyed by (owner, repo, sha), so that
+    /// it can be completed rather than left behind
+    check_runs: Arc<Mutex<HashMap<(String, String, String), u64>>>,
 }
 
 impl Worker {
+    fn new(statuses: mpsc::Sender<Job>, journal: Journal, checks: bool) -> Worker {
+        Worker {
+            evaluations: Arc::new(Queue::new()),
+            statuses,
+            pending_statuses: Arc::new(AtomicUsize::new(0)),
+            journal: Arc::new(journal),
+            checks,
+            queued: Arc::new(Mutex::new(HashMap::new())),
+            check_runs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Marks the head of the pull request as pending and queues it for evaluation.
+    pub fn queue_evaluation(&self, job: PullRequestJob) -> Result<()> {
+        self.queue_status(
+            State::Pending,
+            "The pull request has been received".to_string(),
+            None,
+            Commit {
+                owner: job.owner.clone(),
+                repo: job.repo.clone(),
+                sha: job.head_sha.clone(),
+                installation: job.installation,
+            },
+        )?;
+        self.journal.pending(&job);
+        self.queue_pull_request(job)
+    }
+
+    /// Queues the work which was outstanding when Tailor last stopped. Pull requests which were
+    /// left pending are refreshed, since their heads may have changed in the meantime.
+    pub fn recover(&self, recovery: Recovery) -> Result<()> {
+        info!(
+            "Recovering {} queued jobs and {} pending pull requests",
+            recovery.jobs.len(),
+            recovery.pending.len()
+        );
+
+        for job in recovery.jobs {
+            match job {
+                journal::Job::PullRequest(job) => self.queue_pull_request(job)?,
+                journal::Job::Refresh(job) => self.queue_refresh(job)?,
+            }
+        }
+        for job in recovery.pending {
+            self.queue_refresh(job)?;
+        }
+        self.journal.recovered();
+        Ok(())
+    }
+
+    /// Queues the pull request for evaluation once its head has been looked up.
+    pub fn queue_refresh(&self, job: RefreshJob) -> Result<()> {
+        debug!("Queuing refresh of {:?}", job);
+        let id = self.journal.queued(journal::Job::Refresh(job.clone()));
+        self.evaluations.push(id, Job::Refresh(job));
+        Ok(())
+    }
+
+    /// Queues the pull request for evaluation, unless the same head is already waiting to be
+    /// evaluated. Any queued jobs for earlier heads are superseded and will be skipped.
     pub fn queue_pull_request(&self, job: PullRequestJob) -> Result<()> {
+        match sync::lock(&self.queued).insert(job.key(), job.head_sha.clone()) {
+            Some(ref head) if *head == job.head_sha => {
+                debug!("Pull request {:?} is already queued", job);
+                return Ok(());
+            }
+            Some(head) => debug!("Superseding queued pull request head {}", head),
+            None => {}
+        }
+
         debug!("Queuing pull request {:?}", job);
-        self.tx
-            .send(Job::PullRequest(job))
-            .chain_err(|| "Failed to queue pull request")
+        let id = self.journal.queued(journal::Job::PullRequest(job.clone()));
+        self.evaluations.push(id, Job::PullRequest(job));
+        Ok(())
+    }
+
+    /// Takes the job off of the queue, returning false if it has been superseded.
+    fn start(&self, job: &PullRequestJob) -> bool {
+        let mut queued = sync::lock(&self.queued);
+        let current = match queued.get(&job.key()) {
+            Some(head) => *head == job.head_sha,
+            None => true,
+        };
+        if current {
+            queued.remove(&job.key());
+        }
+        current
+    }
+
+    /// Whether the pull request has been queued again since the job was started, in which case the
+    /// newer job will report on it instead.
+    fn superseded(&self, job: &PullRequestJob) -> bool {
+        sync::lock(&self.queued).contains_key(&job.key())
+    }
+
+    /// Stops tracking the pending status of a superseded job, since it will never be reported on
+    /// (its replacement will be instead).
+    fn forget(&self, job: &PullRequestJob) {
+        self.journal
+            .reported(&job.owner, &job.repo, &job.head_sha);
+        if !self.checks {
+            return;
+        }
+
+        // The check run would otherwise be left in progress
+        debug!("Queuing completion of superseded check run for {:?}", job);
+        if let Err(err) = self.send_status(Job::CheckRun(CheckRunJob {
+            check_run: CheckRun {
+                name: "tailor".to_string(),
+                status: CheckStatus::Completed,
+                conclusion: Some(Conclusion::Neutral),
+                details_url: None,
+                output: CheckOutput {
+                    title: "Superseded".to_string(),
+                    summary: "The pull request was updated before this commit was evaluated."
+                        .to_string(),
+                    text: None,
+                },
+            },
+            create: false,
+            commit: Commit {
+                owner: job.owner.clone(),
+                repo: job.repo.clone(),
+                sha: job.head_sha.clone(),
+                installation: job.installation,
+            },
+        })) {
+            error!("Failed to queue completion of check run: {}", err);
+        }
+    }
+
+    /// Stops tracking the pending status of the commit once its final status couldn't be posted,
+    /// so that the journal doesn't hold on to it forever. The status stays pending on GitHub until
+    /// the pull request is evaluated again.
+    fn abandon(&self, commit: &Commit) {
+        warn!("Abandoning pending status of {:?}", commit);
+        self.journal
+            .reported(&commit.owner, &commit.repo, &commit.sha);
     }
 
     pub fn queue_status(
@@ -45,18 +207,183 @@ impl Worker {
         url: Option<String>,
         commit: Commit,
     ) -> Result<()> {
+        if self.checks {
+            let summary = description.clone();
+            return self.queue_check_run(state, description, summary, None, url, commit);
+        }
+
         debug!("Queuing status {:?} for {:?}", state, commit);
-        self.tx
-            .send(Job::Status(StatusJob {
-                status: Status {
-                    state: state,
-                    description: description,
-                    target_url: url,
-                    context: "tailor".to_string(),
+        self.send_status(Job::Status(StatusJob {
+            status: Status {
+                state: state,
+                description: description,
+                target_url: url,
+                context: "tailor".to_string(),
+            },
+            commit,
+        })).chain_err(|| "Failed to queue status")
+    }
+
+    /// Queues the check run for the commit. The run which is created while the commit is pending
+    /// is updated once it completes, rather than a second run being created (even if Tailor was
+    /// restarted in the meantime).
+    pub fn queue_check_run(
+        &self,
+        state: State,
+        title: String,
+        summary: String,
+        text: Option<String>,
+        url: Option<String>,
+        commit: Commit,
+    ) -> Result<()> {
+        debug!("Queuing check run {:?} for {:?}", state, commit);
+        let (status, conclusion) = match state {
+            State::Pending => (CheckStatus::InProgress, None),
+            State::Success => (CheckStatus::Completed, Some(Conclusion::Success)),
+            State::Failure | State::Error => (CheckStatus::Completed, Some(Conclusion::Failure)),
+        };
+
+        self.send_status(Job::CheckRun(CheckRunJob {
+            check_run: CheckRun {
+                name: "tailor".to_string(),
+                status,
+                conclusion,
+                details_url: url,
+                output: CheckOutput {
+                    title,
+                    summary,
+                    text,
                 },
-                commit,
-            }))
-            .chain_err(|| "Failed to queue status")
+            },
+            create: true,
+            commit,
+        })).chain_err(|| "Failed to queue check run")
+    }
+
+    /// Queues the summary comment, which replaces any earlier summary on the pull request. A new
+    /// comment is only created if `create` is set; otherwise, only an existing one is updated.
+    pub fn queue_comment(
+        &self,
+        number: usize,
+        body: String,
+        create: bool,
+        commit: Commit,
+    ) -> Result<()> {
+        debug!("Queuing summary comment for {:?}", commit);
+        self.send_status(Job::Comment(CommentJob {
+            number,
+            body,
+            create,
+            commit,
+        })).chain_err(|| "Failed to queue comment")
+    }
+
+    fn send_status(&self, job: Job) -> ::std::result::Result<(), mpsc::SendError<Job>> {
+        self.pending_statuses.fetch_add(1, Ordering::SeqCst);
+        self.statuses.send(job).map_err(|err| {
+            self.pending_statuses.fetch_sub(1, Ordering::SeqCst);
+            err
+        })
+    }
+
+    /// The number of jobs which are waiting for (or being processed by) the background threads.
+    pub fn depth(&self) -> QueueDepth {
+        let (queued, running) = self.evaluations.depth();
+        QueueDepth {
+            queued,
+            running,
+            statuses: self.pending_statuses.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueDepth {
+    /// Evaluations (and refreshes) waiting for a thread
+    pub queued: usize,
+    /// Evaluations (and refreshes) in progress
+    pub running: usize,
+    /// Statuses, check runs, and comments waiting to be posted
+    pub statuses: usize,
+}
+
+/// The jobs waiting for a thread from the pool. Jobs are taken in the order in which they were
+/// queued, except that only one job at a time is run for each repository; later jobs for a busy
+/// repository wait for it to finish, letting jobs for other repositories go ahead.
+struct Queue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// Each job, along with its ID in the journal
+    jobs: VecDeque<(u64, Job)>,
+    /// The repositories with a job in progress, as (owner, repo)
+    busy: HashSet<(String, String)>,
+}
+
+/// A job taken from the queue, whose repository is released once the job is dropped (even if
+/// processing it panics).
+struct Running<'a> {
+    queue: &'a Queue,
+    repository: (String, String),
+}
+
+impl Queue {
+    fn new() -> Queue {
+        Queue {
+            state: Mutex::new(QueueState::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn push(&self, id: u64, job: Job) {
+        sync::lock(&self.state).jobs.push_back((id, job));
+        self.ready.notify_one();
+    }
+
+    /// Waits for a job whose repository isn't busy, and marks its repository as busy.
+    fn pop(&self) -> (u64, Job, Running) {
+        let mut state = sync::lock(&self.state);
+        loop {
+            let next = {
+                let busy = &state.busy;
+                state
+                    .jobs
+                    .iter()
+                    .position(|&(_, ref job)| !busy.contains(&job.repository()))
+            };
+
+            if let Some((id, job)) = next.and_then(|i| state.jobs.remove(i)) {
+                let repository = job.repository();
+                state.busy.insert(repository.clone());
+                return (
+                    id,
+                    job,
+                    Running {
+                        queue: self,
+                        repository,
+                    },
+                );
+            }
+
+            state = sync::wait(&self.ready, state);
+        }
+    }
+
+    /// The number of queued and running jobs.
+    fn depth(&self) -> (usize, usize) {
+        let state = sync::lock(&self.state);
+        (state.jobs.len(), state.busy.len())
+    }
+}
+
+impl<'a> Drop for Running<'a> {
+    fn drop(&mut self) {
+        sync::lock(&self.queue.state).busy.remove(&self.repository);
+        // Any of the waiting threads may be able to take a job for this repository
+        self.queue.ready.notify_all();
     }
 }
 
@@ -66,7 +393,24 @@ impl iron::typemap::Key for Worker {
 
 pub enum Job {
     Status(StatusJob),
+    CheckRun(CheckRunJob),
+    Comment(CommentJob),
     PullRequest(PullRequestJob),
+    Refresh(RefreshJob),
+}
+
+impl Job {
+    /// The repository (as owner and repo) to which the job applies.
+    fn repository(&self) -> (String, String) {
+        let (owner, repo) = match *self {
+            Job::Status(ref job) => (&job.commit.owner, &job.commit.repo),
+            Job::CheckRun(ref job) => (&job.commit.owner, &job.commit.repo),
+            Job::Comment(ref job) => (&job.commit.owner, &job.commit.repo),
+            Job::PullRequest(ref job) => (&job.owner, &job.repo),
+            Job::Refresh(ref job) => (&job.owner, &job.repo),
+        };
+        (owner.clone(), repo.clone())
+    }
 }
 
 pub struct StatusJob {
@@ -74,11 +418,34 @@ pub struct StatusJob {
     pub commit: Commit,
 }
 
+pub struct CheckRunJob {
+    pub check_run: CheckRun,
+    /// Whether to create the check run if there isn't one in progress
+    pub create: bool,
+    pub commit: Commit,
+}
+
+pub struct CommentJob {
+    pub number: usize,
+    pub body: String,
+    /// Whether to create the comment if the pull request doesn't have one yet
+    pub create: bool,
+    pub commit: Commit,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct PullRequestJob {
     pub owner: String,
     pub repo: String,
     pub number: usize,
     pub head_sha: String,
+    pub installation: Option<u64>,
+}
+
+impl PullRequestJob {
+    fn key(&self) -> (String, String, usize) {
+        (self.owner.clone(), self.repo.clone(), self.number)
+    }
 }
 
 impl fmt::Debug for PullRequestJob {
@@ -91,6 +458,20 @@ impl fmt::Debug for PullRequestJob {
     }
 }
 
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RefreshJob {
+    pub owner: String,
+    pub repo: String,
+    pub number: usize,
+    pub installation: Option<u64>,
+}
+
+impl fmt::Debug for RefreshJob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pull Request {}/{}: {}", self.owner, self.repo, self.number)
+    }
+}
+
 #[derive(Serialize)]
 pub struct Status {
     pub state: State,
@@ -99,7 +480,7 @@ pub struct Status {
     pub target_url: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub enum State {
     #[serde(rename = "success")]
     Success,
@@ -111,10 +492,56 @@ pub enum State {
     Error,
 }
 
+#[derive(Serialize)]
+pub struct CheckRun {
+    pub name: String,
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conclusion: Option<Conclusion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details_url: Option<String>,
+    pub output: CheckOutput,
+}
+
+#[derive(Serialize)]
+pub struct CheckOutput {
+    pub title: String,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// The body of the request which creates a check run.
+#[derive(Serialize)]
+struct NewCheckRun<'a> {
+    head_sha: &'a str,
+    #[serde(flatten)]
+    check_run: &'a CheckRun,
+}
+
+#[derive(Debug, Serialize)]
+pub enum CheckStatus {
+    #[serde(rename = "in_progress")]
+    InProgress,
+    #[serde(rename = "completed")]
+    Completed,
+}
+
+#[derive(Debug, Serialize)]
+pub enum Conclusion {
+    #[serde(rename = "success")]
+    Success,
+    #[serde(rename = "failure")]
+    Failure,
+    #[serde(rename = "neutral")]
+    Neutral,
+}
+
 pub struct Commit {
     pub owner: String,
     pub repo: String,
     pub sha: String,
+    pub installation: Option<u64>,
 }
 
 impl fmt::Debug for Commit {
@@ -123,104 +550,533 @@ impl fmt::Debug for Commit {
     }
 }
 
-pub fn spawn(access_token: String, address: String) -> Result<Worker> {
-    let (tx, rx) = mpsc::channel::<Job>();
+/// Deployment-wide settings which control how pull requests are evaluated and reported.
+pub struct Settings {
+    /// The URL at which the server is reached, without a trailing slash
+    pub public_url: String,
+    /// The login with which Tailor comments, so that its own comments can be recognized
+    pub login: String,
+    /// Whether results are reported as check runs instead of commit statuses
+    pub checks: bool,
+    /// The revision from which each repository's configuration is loaded
+    pub config_source: ConfigSource,
+    /// The maximum number of items (e.g. commits) fetched from any one listing
+    pub max_items: usize,
+    /// The number of threads which evaluate pull requests
+    pub threads: usize,
+}
+
+pub fn spawn(
+    auth: Authenticator,
+    settings: Settings,
+    cache: PermissionCache,
+    results: ResultStore,
+    journal: Journal,
+) -> Result<Worker> {
+    let (statuses, rx) = mpsc::channel::<Job>();
 
-    let worker = Worker { tx };
-    let worker_internal = worker.clone();
-    thread::Builder::new()
-        .name("Status Worker".to_string())
-        .spawn(move || {
-            let client = client::Github::new(&access_token).expect("github client");
-            loop {
-                match rx.recv() {
-                    Ok(Job::Status(job)) => process_status(&client, job),
-                    Ok(Job::PullRequest(job)) => {
-                        process_pull_request(&client, &worker_internal, &address, job)
-                    }
-                    Err(err) => error!("Error receiving job: {}", err),
+    let worker = Worker::new(statuses, journal, settings.checks);
+    let auth = Arc::new(auth);
+    let settings = Arc::new(settings);
+    let reactions = ReactionCache::new(MAX_REACTIONS);
+
+    {
+        let worker = worker.clone();
+        let auth = auth.clone();
+        let settings = settings.clone();
+        let cache = cache.clone();
+        let reactions = reactions.clone();
+        let results = results.clone();
+        thread::Builder::new()
+            .name("Status Worker".to_string())
+            .spawn(move || {
+                for job in rx.iter() {
+                    worker.pending_statuses.fetch_sub(1, Ordering::SeqCst);
+                    process(&auth, &worker, &settings, &cache, &reactions, &results, job);
                 }
-            }
-        })
-        .chain_err(|| "Failed to start status worker")?;
+            })
+            .chain_err(|| "Failed to start status worker")?;
+    }
+
+    for i in 0..settings.threads {
+        let worker = worker.clone();
+        let auth = auth.clone();
+        let settings = settings.clone();
+        let cache = cache.clone();
+        let reactions = reactions.clone();
+        let results = results.clone();
+        thread::Builder::new()
+            .name(format!("Evaluation Worker {}", i))
+            .spawn(move || loop {
+                let (id, job, _running) = worker.evaluations.pop();
+                debug!("Queue depth: {:?}", worker.depth());
+                process(&auth, &worker, &settings, &cache, &reactions, &results, job);
+                worker.journal.finished(id);
+            })
+            .chain_err(|| "Failed to start evaluation worker")?;
+    }
 
     Ok(worker)
 }
 
-fn process_status(client: &client::Github, job: StatusJob) {
+/// Processes the job, catching any panic so that the thread lives on to process the next one.
+fn process(
+    auth: &Authenticator,
+    worker: &Worker,
+    settings: &Settings,
+    cache: &PermissionCache,
+    reactions: &ReactionCache,
+    results: &ResultStore,
+    job: Job,
+) {
+    let (owner, repo) = job.repository();
+    if panic::catch_unwind(AssertUnwindSafe(|| {
+        process_job(auth, worker, settings, cache, reactions, results, job)
+    })).is_err()
+    {
+        error!("Panicked while processing job for {}/{}", owner, repo);
+    }
+}
+
+fn process_job(
+    auth: &Authenticator,
+    worker: &Worker,
+    settings: &Settings,
+    cache: &PermissionCache,
+    reactions: &ReactionCache,
+    results: &ResultStore,
+    job: Job,
+) {
+    let installation = match job {
+        Job::Status(ref job) => job.commit.installation,
+        Job::CheckRun(ref job) => job.commit.installation,
+        Job::Comment(ref job) => job.commit.installation,
+        Job::PullRequest(ref job) => job.installation,
+        Job::Refresh(ref job) => job.installation,
+    };
+
+    let client = match auth.client(installation) {
+        Ok(client) => client,
+        Err(err) => {
+            let (owner, repo) = job.repository();
+            error!("Failed to authenticate for {}/{}: {}", owner, repo, err);
+            match job {
+                Job::Status(ref job) if job.status.state != State::Pending => {
+                    worker.abandon(&job.commit)
+                }
+                Job::CheckRun(ref job) if job.check_run.conclusion.is_some() => {
+                    worker.abandon(&job.commit)
+                }
+                Job::PullRequest(ref job) => worker.forget(job),
+                _ => {}
+            }
+            return;
+        }
+    };
+
+    match job {
+        Job::Status(job) => process_status(&client, worker, job),
+        Job::CheckRun(job) => process_check_run(&client, worker, job),
+        Job::Comment(job) => process_comment(&client, settings, job),
+        Job::PullRequest(job) => {
+            process_pull_request(&client, worker, settings, cache, reactions, results, job)
+        }
+        Job::Refresh(job) => process_refresh(&client, worker, job),
+    }
+}
+
+fn process_status(client: &Github, worker: &Worker, job: StatusJob) {
     debug!(
         "Processing status {:?} for {:?}",
         job.status.state, job.commit
     );
 
-    if let Err(err) = client
-        .post(job.status)
-        .repos()
-        .owner(&job.commit.owner)
-        .repo(&job.commit.repo)
-        .statuses()
-        .sha(&job.commit.sha)
+    // Setting the same status twice is harmless, so this can be retried
+    let endpoint = format!(
+        "repos/{}/{}/statuses/{}",
+        job.commit.owner, job.commit.repo, job.commit.sha
+    );
+    match github::retry(|| client.post(&endpoint, &job.status))
+        .max_backoff(MAX_STATUS_BACKOFF_SECS)
         .try_execute::<Empty>()
     {
-        error!("Failed to set status: {}", err)
+        Ok(_) => match job.status.state {
+            State::Pending => {}
+            _ => worker
+                .journal
+                .reported(&job.commit.owner, &job.commit.repo, &job.commit.sha),
+        },
+        Err(err) => {
+            error!("Failed to set status: {}", err);
+            if job.status.state != State::Pending {
+                worker.abandon(&job.commit);
+            }
+        }
+    }
+}
+
+fn process_check_run(client: &Github, worker: &Worker, job: CheckRunJob) {
+    debug!(
+        "Processing check run {:?} for {:?}",
+        job.check_run.status, job.commit
+    );
+
+    let key = (
+        job.commit.owner.clone(),
+        job.commit.repo.clone(),
+        job.commit.sha.clone(),
+    );
+    let completed = job.check_run.conclusion.is_some();
+    let existing = if completed {
+        sync::lock(&worker.check_runs).remove(&key)
+    } else {
+        sync::lock(&worker.check_runs).get(&key).cloned()
+    };
+    // The ID is only remembered until Tailor restarts, so look for a run which was left behind
+    let existing = existing.or_else(|| find_check_run(client, &job.commit));
+    if existing.is_none() && !job.create {
+        debug!("No check run to update for {:?}", job.commit);
+        return;
+    }
+
+    let result = match existing {
+        // Completing the run twice is harmless, so this can be retried
+        Some(id) => {
+            let endpoint = format!(
+                "repos/{}/{}/check-runs/{}",
+                job.commit.owner, job.commit.repo, id
+            );
+            github::retry(|| client.patch(&endpoint, &job.check_run))
+                .max_backoff(MAX_STATUS_BACKOFF_SECS)
+                .try_execute::<Empty>()
+                .map(|_| id)
+        }
+        None => client
+            .post(
+                &format!("repos/{}/{}/check-runs", job.commit.owner, job.commit.repo),
+                NewCheckRun {
+                    head_sha: &job.commit.sha,
+                    check_run: &job.check_run,
+                },
+            )
+            .try_execute::<types::CheckRun>()
+            .map(|run| run.id),
+    };
+
+    match result {
+        Ok(_) if completed => worker
+            .journal
+            .reported(&job.commit.owner, &job.commit.repo, &job.commit.sha),
+        Ok(id) => {
+            sync::lock(&worker.check_runs).insert(key, id);
+        }
+        Err(err) => {
+            error!("Failed to report check run: {}", err);
+            if completed {
+                worker.abandon(&job.commit);
+            }
+        }
+    }
+}
+
+/// Finds Tailor's in-progress check run for the commit, if there is one.
+fn find_check_run(client: &Github, commit: &Commit) -> Option<u64> {
+    let endpoint = format!(
+        "repos/{}/{}/commits/{}/check-runs?check_name=tailor&status=in_progress",
+        commit.owner, commit.repo, commit.sha
+    );
+    match github::retry(|| client.get(&endpoint))
+        .max_backoff(MAX_STATUS_BACKOFF_SECS)
+        .try_execute::<types::CheckRuns>()
+    {
+        Ok(runs) => runs.check_runs.first().map(|run| run.id),
+        Err(err) => {
+            warn!("Failed to look up check runs for {:?}: {}", commit, err);
+            None
+        }
+    }
+}
+
+fn process_comment(client: &Github, settings: &Settings, job: CommentJob) {
+    debug!("Processing summary comment for {:?}", job.commit);
+
+    let comments: Vec<types::Comment> = match github::paginate(
+        client,
+        &format!(
+            "repos/{}/{}/issues/{}/comments",
+            job.commit.owner, job.commit.repo, job.number
+        ),
+        settings.max_items,
+    ) {
+        Ok(comments) => comments,
+        Err(err) => {
+            error!("Failed to fetch pull request comments: {}", err);
+            return;
+        }
+    };
+
+    let body = json!({ "body": job.body });
+    // Anyone could copy the marker, so only Tailor's own comment is updated
+    let result = match comments.iter().find(|comment| {
+        comment.user.login == settings.login && comment.body.contains(report::COMMENT_MARKER)
+    }) {
+        Some(comment) if comment.body == job.body => {
+            debug!("Summary comment {} is up to date", comment.id);
+            return;
+        }
+        // Replacing the body of the comment is harmless, so this can be retried
+        Some(comment) => {
+            let endpoint = format!(
+                "repos/{}/{}/issues/comments/{}",
+                job.commit.owner, job.commit.repo, comment.id
+            );
+            github::retry(|| client.patch(&endpoint, &body))
+                .max_backoff(MAX_STATUS_BACKOFF_SECS)
+                .try_execute::<Empty>()
+        }
+        None if job.create => client
+            .post(
+                &format!(
+                    "repos/{}/{}/issues/{}/comments",
+                    job.commit.owner, job.commit.repo, job.number
+                ),
+                &body,
+            )
+            .try_execute::<Empty>(),
+        None => return,
+    };
+
+    if let Err(err) = result {
+        error!("Failed to post summary comment: {}", err);
+    }
+}
+
+fn process_refresh(client: &Github, worker: &Worker, job: RefreshJob) {
+    debug!("Processing refresh of {:?}", job);
+
+    let endpoint = format!("repos/{}/{}/pulls/{}", job.owner, job.repo, job.number);
+    let pr: types::PullRequest = match github::retry(|| client.get(&endpoint)).try_execute() {
+        Ok(pr) => pr,
+        Err(err) => {
+            error!("Failed to fetch {:?}: {}", job, err);
+            return;
+        }
+    };
+
+    if pr.state != "open" {
+        debug!("Ignoring refresh of closed {:?}", job);
+        return;
+    }
+
+    if let Err(err) = worker.queue_evaluation(PullRequestJob {
+        owner: job.owner,
+        repo: job.repo,
+        number: job.number,
+        head_sha: pr.head.sha,
+        installation: job.installation,
+    }) {
+        error!("Failed to queue pull request: {}", err);
     }
 }
 
 fn process_pull_request(
-    client: &client::Github,
+    client: &Github,
     worker: &Worker,
-    address: &str,
+    settings: &Settings,
+    cache: &PermissionCache,
+    reactions: &ReactionCache,
+    results: &ResultStore,
     job: PullRequestJob,
 ) {
+    if !worker.start(&job) {
+        debug!("Skipping superseded pull request {:?}", job);
+        worker.forget(&job);
+        return;
+    }
+
     debug!("Processing pull request {:?}", job);
 
-    fn create_status_url(failures: &str, address: &str) -> Result<String> {
-        let compressed = snap::Encoder::new()
-            .compress_vec(failures.as_bytes())
-            .chain_err(|| "Failed to compress message")?;
+    // Stores the result so that the status can link to a page describing it
+    let create_status_url = |description: &str, lines, report: Option<&Report>| -> Result<String> {
+        let id = results.save(&StoredResult {
+            owner: job.owner.clone(),
+            repo: job.repo.clone(),
+            number: job.number,
+            head_sha: job.head_sha.clone(),
+            created_at: Utc::now(),
+            description: description.to_string(),
+            lines,
+            report: report.cloned(),
+        })?;
+        Ok(format!("{}/status/{}", settings.public_url, id))
+    };
 
-        Ok(format!(
-            "http://{}/status?snap={}",
-            address,
-            base64::encode_config(&compressed, base64::URL_SAFE_NO_PAD),
-        ))
-    }
+    let (state, description, summary, details, url, comment) = match match github::validate::pull_request(
+        &job, client, settings, cache, reactions,
+    ) {
+        Ok(report) => {
+            let state = if report.failures().is_empty() {
+                State::Success
+            } else {
+                State::Failure
+            };
+            let description = report.summary();
+            let summary = report.to_markdown();
+            let details = report.to_details();
 
-    let (state, description, url) = match match github::validate::pull_request(&job, client) {
-        Ok(ref failures) if failures.is_empty() => {
-            Ok((State::Success, "All checks passed".into(), None))
+            let lines = report.lines();
+            if lines.is_empty() {
+                Ok(None)
+            } else {
+                create_status_url(&description, lines, Some(&report)).map(Some)
+            }.map(|url| {
+                // The comment is only created once there is something to fix
+                let comment = if report.comment {
+                    Some((
+                        report.to_comment(url.as_ref().map(String::as_str)),
+                        !report.failures().is_empty(),
+                    ))
+                } else {
+                    None
+                };
+                (state, description, summary, Some(details), url, comment)
+            })
+        }
+        Err(Error(ErrorKind::Superseded(head), _)) => {
+            debug!("Skipping pull request {:?}, superseded by {}", job, head);
+            worker.forget(&job);
+            return;
         }
-        Ok(failures) => create_status_url(&failures.join("\n"), address).map(|url| {
-            (
-                State::Failure,
-                "One or more checks failed".into(),
-                Some(url),
-            )
-        }),
         Err(err) => {
             warn!("Failed to evaluate rules: {}", err);
 
-            create_status_url(&err.to_string(), address)
-                .map(|url| (State::Error, "Failed to evaluate rules".into(), Some(url)))
+            let lines = err.to_string().lines().map(String::from).collect();
+            create_status_url("Failed to evaluate rules", lines, None).map(|url| {
+                (
+                    State::Error,
+                    "Failed to evaluate rules".into(),
+                    err.to_string(),
+                    None,
+                    Some(url),
+                    None,
+                )
+            })
         }
     } {
         Ok(status) => status,
         Err(err) => {
             error!("Failed to create status: {}", err);
-            (State::Error, "Failed to create status page".into(), None)
+            (
+                State::Error,
+                "Failed to create status page".into(),
+                err.to_string(),
+                None,
+                None,
+                None,
+            )
         }
     };
 
-    if let Err(err) = worker.queue_status(
-        state,
-        description,
-        url,
-        Commit {
-            owner: job.owner,
-            repo: job.repo,
-            sha: job.head_sha,
-        },
-    ) {
+    debug!("Permission cache: {}", cache.stats());
+
+    if worker.superseded(&job) {
+        debug!("Not reporting on superseded pull request {:?}", job);
+        worker.forget(&job);
+        return;
+    }
+
+    let commit = || Commit {
+        owner: job.owner.clone(),
+        repo: job.repo.clone(),
+        sha: job.head_sha.clone(),
+        installation: job.installation,
+    };
+
+    if let Err(err) = if worker.checks {
+        worker.queue_check_run(state, description, summary, details, url, commit())
+    } else {
+        worker.queue_status(state, description, url, commit())
+    } {
         error!("Failed to queue validation status: {}", err);
+        worker.forget(&job);
+    }
+
+    if let Some((body, create)) = comment {
+        if let Err(err) = worker.queue_comment(job.number, body, create, commit()) {
+            error!("Failed to queue summary comment: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_supersede() {
+        let (tx, _rx) = mpsc::channel();
+        let worker = Worker::new(tx, Journal::disabled(), false);
+        let a = PullRequestJob {
+            owner: "coreos".into(),
+            repo: "tailor".into(),
+            number: 1,
+            head_sha: "a".into(),
+            installation: None,
+        };
+        let b = PullRequestJob {
+            head_sha: "b".into(),
+            ..a.clone()
+        };
+        let c = PullRequestJob {
+            head_sha: "c".into(),
+            ..a.clone()
+        };
+
+        worker.queue_pull_request(a.clone()).unwrap();
+        worker.queue_pull_request(a.clone()).unwrap();
+        worker.queue_pull_request(b.clone()).unwrap();
+        assert_eq!(worker.depth().queued, 2);
+
+        assert!(!worker.start(&a));
+        assert!(worker.start(&b));
+        assert!(!worker.superseded(&b));
+
+        worker.queue_pull_request(c).unwrap();
+        assert!(worker.superseded(&b));
+        assert_eq!(worker.depth().queued, 3);
+    }
+
+    #[test]
+    fn test_queue() {
+        let refresh = |repo: &str, number| {
+            Job::Refresh(RefreshJob {
+                owner: "coreos".into(),
+                repo: repo.into(),
+                number,
+                installation: None,
+            })
+        };
+        let number = |job: &Job| match *job {
+            Job::Refresh(ref job) => job.number,
+            _ => unreachable!(),
+        };
+
+        let queue = Queue::new();
+        queue.push(0, refresh("tailor", 1));
+        queue.push(1, refresh("tailor", 2));
+        queue.push(2, refresh("ignition", 3));
+
+        let (_, first, running) = queue.pop();
+        assert_eq!(number(&first), 1);
+
+        // The second job for tailor has to wait for the first
+        let (_, second, _) = queue.pop();
+        assert_eq!(number(&second), 3);
+        assert_eq!(queue.depth(), (1, 1));
+
+        drop(running);
+        let (_, third, _) = queue.pop();
+        assert_eq!(number(&third), 2);
+        assert_eq!(queue.depth(), (0, 0));
     }
 }